@@ -0,0 +1,80 @@
+//! An ephemeral cgroup v2 group that every run is placed in, to bound the
+//! CPUs and memory available to the benchmarked command (Linux).
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::exit;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Creates the group under the cgroup v2 root, enabling the controllers
+    /// it needs. Requires privileges to write to the hierarchy.
+    pub fn create(cpus: Option<&str>, memory: Option<u64>) -> io::Result<Cgroup> {
+        let root = Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            return Err(io::Error::other("cgroup v2 is not mounted at /sys/fs/cgroup"));
+        }
+        let subtree_control = root.join("cgroup.subtree_control");
+        if cpus.is_some() {
+            fs::write(&subtree_control, "+cpuset")?;
+        }
+        if memory.is_some() {
+            fs::write(&subtree_control, "+memory")?;
+        }
+        let cgroup = Cgroup { path: root.join(format!("avgtime-{}", process::id())) };
+        fs::create_dir(&cgroup.path)?;
+        // A fatal error exits without dropping the group, so remove it then
+        // too.
+        let path = cgroup.path.clone();
+        exit::on_exit(move || {
+            let _ = fs::remove_dir(&path);
+        });
+        if let Some(cpus) = cpus {
+            fs::write(cgroup.path.join("cpuset.cpus"), cpus)?;
+        }
+        if let Some(memory) = memory {
+            fs::write(cgroup.path.join("memory.max"), memory.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    /// Path of the file a process writes to in order to join the group.
+    pub fn procs_path(&self) -> CString {
+        CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes())
+            .expect("cgroup path contains a nul byte")
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Moves the calling process into the group whose `cgroup.procs` is at
+/// `procs`. Only async-signal-safe calls, so it can run between fork and exec.
+pub fn join(procs: &CString) -> io::Result<()> {
+    unsafe {
+        let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+        if written != 1 {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
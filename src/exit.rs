@@ -0,0 +1,48 @@
+//! Process exit codes, so scripts can tell failure categories apart.
+//!
+//! | code | meaning                                 |
+//! |------|-----------------------------------------|
+//! | 0    | success                                 |
+//! | 1    | the benchmarked command failed to run   |
+//! | 2    | invalid arguments or configuration      |
+//! | 3    | a requested check on the results failed |
+//! | 4    | reserved for a detected regression      |
+//! | 5    | the machine was too busy to benchmark   |
+
+use std::fmt::Display;
+use std::mem;
+use std::process;
+use std::sync::Mutex;
+
+/// Undo steps registered with `on_exit`.
+static CLEANUPS: Mutex<Vec<Box<dyn FnOnce() + Send>>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Failure {
+    Command = 1,
+    Usage = 2,
+    Assertion = 3,
+    Busy = 5,
+}
+
+/// Prints `message` to stderr, runs the registered cleanups and exits with
+/// the code for `failure`.
+pub fn exit_with(failure: Failure, message: impl Display) -> ! {
+    eprintln!("avgtime: {}", message);
+    cleanup();
+    process::exit(failure as i32)
+}
+
+/// Registers `undo` to run on the way out, so that changes avgtime makes
+/// outside itself are reverted even when it exits early.
+pub fn on_exit(undo: impl FnOnce() + Send + 'static) {
+    CLEANUPS.lock().unwrap().push(Box::new(undo));
+}
+
+/// Runs the registered cleanups now, most recent first, and forgets them.
+pub fn cleanup() {
+    let undos = mem::take(&mut *CLEANUPS.lock().unwrap());
+    for undo in undos.into_iter().rev() {
+        undo();
+    }
+}
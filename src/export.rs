@@ -0,0 +1,145 @@
+//! Writing results to files for other tools.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::format::NANOS_PER_MS;
+use crate::stats::{percentile, PercentileMethod};
+
+const NANOS_PER_SEC: f64 = 1e9;
+/// How long `write_sqlite` waits for other writers to release the database.
+const SQLITE_BUSY_TIMEOUT_MS: u32 = 5000;
+
+/// One command's results as written by `write_hyperfine`. `times` are in
+/// run order and `ticks` are the same durations sorted. `timestamps`, when
+/// given, are the matching start times.
+pub struct HyperfineResult {
+    pub command: String,
+    pub times: Vec<u128>,
+    pub ticks: Vec<u128>,
+    pub timestamps: Option<Vec<String>>,
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+/// Writes the results in hyperfine's `--export-json` format, with times in
+/// seconds, so they can be fed to its plotting scripts. There is one
+/// element in `results` per benchmarked command; timestamps are added as
+/// an extra field.
+pub fn write_hyperfine(path: &Path, results: &[HyperfineResult]) -> io::Result<()> {
+    let seconds = |nanos: f64| nanos / NANOS_PER_SEC;
+    let mut file = File::create(path)?;
+    writeln!(file, "{{")?;
+    writeln!(file, "  \"results\": [")?;
+    for (i, result) in results.iter().enumerate() {
+        let ticks = &result.ticks;
+        let times: Vec<String> = result.times.iter().map(|t| seconds(*t as f64).to_string()).collect();
+        writeln!(file, "    {{")?;
+        writeln!(file, "      \"command\": {},", json_string(&result.command))?;
+        writeln!(file, "      \"mean\": {},", seconds(result.mean))?;
+        writeln!(file, "      \"stddev\": {},", seconds(result.std_dev))?;
+        writeln!(file, "      \"median\": {},", seconds(percentile(ticks, 0.5, PercentileMethod::Linear)))?;
+        writeln!(file, "      \"min\": {},", seconds(ticks[0] as f64))?;
+        writeln!(file, "      \"max\": {},", seconds(ticks[ticks.len() - 1] as f64))?;
+        match &result.timestamps {
+            Some(timestamps) => {
+                let timestamps: Vec<String> = timestamps.iter().map(|t| json_string(t)).collect();
+                writeln!(file, "      \"times\": [{}],", times.join(", "))?;
+                writeln!(file, "      \"timestamps\": [{}]", timestamps.join(", "))?;
+            }
+            None => writeln!(file, "      \"times\": [{}]", times.join(", "))?,
+        }
+        writeln!(file, "    }}{}", if i + 1 < results.len() { "," } else { "" })?;
+    }
+    writeln!(file, "  ]")?;
+    writeln!(file, "}}")?;
+    Ok(())
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes the sorted durations as whitespace-separated columns for
+/// gnuplot or matplotlib: rank, time in ms and the fraction of runs at or
+/// below it (for a CDF). The summary is included as `#` comments.
+pub fn write_plot_data(path: &Path, command: &str, ticks: &[u128], summary: &[(&str, String)]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "# command: {}", command)?;
+    for (label, value) in summary {
+        writeln!(file, "# {}: {}", label, value)?;
+    }
+    writeln!(file, "# rank\tms\tfraction")?;
+    for (i, tick) in ticks.iter().enumerate() {
+        writeln!(file, "{}\t{}\t{}", i + 1, *tick as f64 / NANOS_PER_MS as f64, (i + 1) as f64 / ticks.len() as f64)?;
+    }
+    Ok(())
+}
+
+/// Appends one row to the `runs` table of the SQLite database at `path`,
+/// creating the database and table when missing. `stats` are column names
+/// with durations in nanoseconds, stored as `<name>_ms` columns, and
+/// `percentiles` the same for columns left NULL when there were too few
+/// runs. The `sqlite3` command-line tool does the writing, waiting for
+/// concurrent writers for up to `SQLITE_BUSY_TIMEOUT_MS`.
+pub fn write_sqlite(
+    path: &Path, timestamp: &str, label: &str, command: &str, runs: usize,
+    stats: &[(&str, f64)], percentiles: &[(&str, Option<f64>)],
+) -> io::Result<()> {
+    let ms = |nanos: f64| (nanos / NANOS_PER_MS as f64).to_string();
+    let columns: Vec<String> = stats.iter().map(|(name, _)| format!("{}_ms REAL NOT NULL", name))
+        .chain(percentiles.iter().map(|(name, _)| format!("{}_ms REAL", name)))
+        .collect();
+    let names: Vec<String> = stats.iter().map(|(name, _)| name).chain(percentiles.iter().map(|(name, _)| name))
+        .map(|name| format!("{}_ms", name))
+        .collect();
+    let values: Vec<String> = stats.iter().map(|(_, nanos)| ms(*nanos))
+        .chain(percentiles.iter().map(|(_, nanos)| nanos.map_or("NULL".to_string(), ms)))
+        .collect();
+    let sql = format!(
+        ".timeout {}\n\
+         BEGIN IMMEDIATE;\n\
+         CREATE TABLE IF NOT EXISTS runs (timestamp TEXT NOT NULL, label TEXT NOT NULL, command TEXT NOT NULL, runs INTEGER NOT NULL, {});\n\
+         INSERT INTO runs (timestamp, label, command, runs, {}) VALUES ({}, {}, {}, {}, {});\n\
+         COMMIT;\n",
+        SQLITE_BUSY_TIMEOUT_MS,
+        columns.join(", "),
+        names.join(", "),
+        sql_string(timestamp), sql_string(label), sql_string(command), runs, values.join(", "));
+    let mut child = Command::new("sqlite3")
+        .arg("-batch")
+        .arg("-bail")
+        .arg(path)
+        .stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(e.kind(), "the sqlite3 command was not found"),
+            _ => e,
+        })?;
+    child.stdin.take().unwrap().write_all(sql.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::other(if message.is_empty() { output.status.to_string() } else { message }));
+    }
+    Ok(())
+}
+
+fn sql_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
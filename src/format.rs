@@ -0,0 +1,137 @@
+//! Rendering of durations in the text report.
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Durations are recorded in nanoseconds and reported in milliseconds.
+pub const NANOS_PER_MS: u128 = 1_000_000;
+
+/// How the integer part of a number is written.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NumberFormat {
+    /// `1234567.000`
+    Plain,
+    /// `1,234,567.000`
+    Grouped,
+}
+
+impl NumberFormat {
+    pub const NAMES: &'static [&'static str] = &["plain", "grouped"];
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(NumberFormat::Plain),
+            "grouped" => Ok(NumberFormat::Grouped),
+            _ => Err(format!("unknown number format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeFormat {
+    pub precision: usize,
+    pub numbers: NumberFormat,
+}
+
+impl TimeFormat {
+    /// Formats `nanos` as milliseconds, e.g. `12.347ms`.
+    pub fn ms(&self, nanos: f64) -> String {
+        self.ms_with_precision(nanos, self.precision)
+    }
+
+    pub fn ms_with_precision(&self, nanos: f64, precision: usize) -> String {
+        format!("{}ms", TimeFormat { precision, ..*self }.number(nanos))
+    }
+
+    /// Formats `nanos` as a number of milliseconds without the unit.
+    pub fn number(&self, nanos: f64) -> String {
+        let number = format!("{:.*}", self.precision, nanos / NANOS_PER_MS as f64);
+        match self.numbers {
+            NumberFormat::Plain => number,
+            NumberFormat::Grouped => group_thousands(&number),
+        }
+    }
+}
+
+fn group_thousands(number: &str) -> String {
+    let (sign, unsigned) = match number.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", number),
+    };
+    let (integer, fraction) = match unsigned.find('.') {
+        Some(dot) => unsigned.split_at(dot),
+        None => (unsigned, ""),
+    };
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+/// Prints `label: value` summary lines, one per line or, when `compact`,
+/// two per line with the columns aligned.
+pub fn print_summary(lines: &[(&str, String)], compact: bool) {
+    let cells: Vec<String> = lines.iter().map(|(label, value)| format!("{}: {}", label, value)).collect();
+    if !compact {
+        for cell in &cells {
+            println!("{}", cell);
+        }
+        return;
+    }
+    let width = cells.iter().step_by(2).map(|cell| cell.len()).max().unwrap_or(0);
+    for row in cells.chunks(2) {
+        match row {
+            [left, right] => println!("{:<width$}   {}", left, right, width = width),
+            [left] => println!("{}", left),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Replaces every `{name}` in `template` with its value from `values`,
+/// rejecting names that are not listed there.
+pub fn fill_template(template: &str, values: &[(&str, String)]) -> Result<String, String> {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let end = start + rest[start..].find('}').ok_or_else(|| format!("unclosed {{ in template: {}", template))?;
+        let name = &rest[start + 1..end];
+        let (_, value) = values.iter().find(|(key, _)| *key == name).ok_or_else(|| {
+            let names: Vec<String> = values.iter().map(|(key, _)| format!("{{{}}}", key)).collect();
+            format!("unknown placeholder {{{}}}, valid names are {}", name, names.join(", "))
+        })?;
+        filled.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+/// Formats a wall-clock time as RFC 3339 in UTC with milliseconds, e.g.
+/// `2024-05-01T12:30:00.250Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, of_day) = ((seconds / 86_400) as i64, seconds % 86_400);
+    // Civil date from the day number, after Howard Hinnant's days_from_civil.
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, of_day / 3600, of_day % 3600 / 60, of_day % 60, since_epoch.subsec_millis())
+}
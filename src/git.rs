@@ -0,0 +1,27 @@
+//! Checking out revisions for `--git-bench`.
+
+use std::io;
+use std::process::Command;
+
+/// Runs git with `args` in the current directory and returns its output.
+fn git(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Returns the checked out branch, or the commit when HEAD is detached.
+pub fn current_ref() -> io::Result<String> {
+    git(&["symbolic-ref", "-q", "--short", "HEAD"]).or_else(|_| git(&["rev-parse", "HEAD"]))
+}
+
+/// Whether tracked files have uncommitted changes.
+pub fn is_dirty() -> io::Result<bool> {
+    Ok(!git(&["status", "--porcelain", "--untracked-files=no"])?.is_empty())
+}
+
+pub fn checkout(reference: &str) -> io::Result<()> {
+    git(&["checkout", "-q", reference]).map(drop)
+}
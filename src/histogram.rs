@@ -0,0 +1,161 @@
+//! Text histogram of the run durations.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::format::TimeFormat;
+
+/// Upper bound on the number of bins picked by `bucket_width`.
+const MAX_BINS: u128 = 40;
+/// Length of the bar for the most frequent bin.
+const BAR_WIDTH: u128 = 40;
+
+/// Order of the histogram rows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HistogramSort {
+    /// Ascending time.
+    Time,
+    /// Most frequent bin first, ties in ascending time.
+    Count,
+}
+
+impl HistogramSort {
+    pub const NAMES: &'static [&'static str] = &["time", "count"];
+}
+
+impl FromStr for HistogramSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "time" => Ok(HistogramSort::Time),
+            "count" => Ok(HistogramSort::Count),
+            _ => Err(format!("unknown histogram order: {}", s)),
+        }
+    }
+}
+
+/// Picks a bucket width in nanoseconds from the 1-2-5 series so that the
+/// span of the data fits in at most `MAX_BINS` bins.
+pub fn bucket_width(span: u128) -> u128 {
+    let mut magnitude: u128 = 1;
+    loop {
+        for step in &[1, 2, 5] {
+            let width = magnitude * step;
+            if span / width < MAX_BINS {
+                return width;
+            }
+        }
+        magnitude *= 10;
+    }
+}
+
+/// Counts `ticks` into bins of `width` nanoseconds, keyed by bin start.
+pub fn bins(ticks: &[u128], width: u128) -> BTreeMap<u128, u128> {
+    let mut bins = BTreeMap::new();
+    for tick in ticks {
+        *bins.entry(tick / width * width).or_insert(0) += 1;
+    }
+    bins
+}
+
+/// A `#` bar for `value`, scaled so that `max` gets the full width.
+pub fn bar(value: u128, max: u128) -> String {
+    "#".repeat((value * BAR_WIDTH / max.max(1)) as usize)
+}
+
+/// Prints the histogram. Bins are `fixed_width` nanoseconds wide when
+/// given, otherwise sized to the data. Fixed-width bins start at the bin of
+/// the fastest run; runs beyond `MAX_BINS` bins are counted in a final
+/// catch-all bin. Each of `markers`, a label and a value, is shown next to
+/// the bin holding its value, or the closest shown bin below it. Rows are
+/// listed in `sort` order.
+pub fn print(ticks: &[u128], time: &TimeFormat, fixed_width: Option<u128>, markers: &[(&str, f64)], sort: HistogramSort) {
+    let min = *ticks.first().unwrap();
+    let width = fixed_width.unwrap_or_else(|| bucket_width(ticks.last().unwrap() - min));
+    let limit = min / width * width + MAX_BINS * width;
+    let (binned, overflow) = match fixed_width {
+        Some(_) => ticks.split_at(ticks.partition_point(|tick| *tick < limit)),
+        None => (ticks, &[][..]),
+    };
+    let bins = bins(binned, width);
+    let max_freq = bins.values().copied().max().unwrap_or(0).max(overflow.len() as u128);
+    // Enough decimal places in the millisecond labels to tell bins apart.
+    let precision = 6usize.saturating_sub((width as f64).log10().floor() as usize);
+    let mut labels: BTreeMap<Option<u128>, Vec<&str>> = BTreeMap::new();
+    for (label, value) in markers {
+        let value = *value as u128;
+        let bin = if !overflow.is_empty() && value >= limit {
+            None
+        } else {
+            bins.range(..=value).next_back().or_else(|| bins.iter().next()).map(|(start, _)| *start)
+        };
+        labels.entry(bin).or_default().push(label);
+    }
+    let annotation = |bin| match labels.get(&bin) {
+        Some(labels) => format!(" ← {}", labels.join(", ")),
+        None => String::new(),
+    };
+
+    let mut rows: Vec<(String, u128, String)> = bins.iter()
+        .map(|(start, count)| (time.ms_with_precision(*start as f64, precision), *count, annotation(Some(*start))))
+        .collect();
+    if !overflow.is_empty() {
+        rows.push((format!(">={}", time.ms_with_precision(limit as f64, precision)), overflow.len() as u128, annotation(None)));
+    }
+    if sort == HistogramSort::Count {
+        rows.sort_by_key(|(_, count, _)| Reverse(*count));
+    }
+
+    println!("Histogram:");
+    println!("time:	count	normalized bar");
+    for (start, count, annotation) in rows {
+        println!("{}	{}	{}{}", start, count, bar(count, max_freq), annotation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_width_of_zero_span() {
+        assert_eq!(bucket_width(0), 1);
+    }
+
+    #[test]
+    fn bucket_width_for_nanosecond_spans() {
+        assert_eq!(bucket_width(39), 1);
+        assert_eq!(bucket_width(40), 2);
+        assert_eq!(bucket_width(150), 5);
+        assert_eq!(bucket_width(999), 50);
+    }
+
+    #[test]
+    fn bucket_width_for_second_spans() {
+        // 3s of data fits into 40 bins of 100ms.
+        assert_eq!(bucket_width(3_000_000_000), 100_000_000);
+        assert_eq!(bucket_width(10_000_000_000), 500_000_000);
+    }
+
+    #[test]
+    fn bucket_width_keeps_bins_under_the_limit() {
+        for span in [0, 1, 7, 480, 12_345, 2_500_000, 987_654_321, 60_000_000_000] {
+            assert!(span / bucket_width(span) < MAX_BINS, "span {}", span);
+        }
+    }
+
+    #[test]
+    fn bins_of_sub_microsecond_ticks() {
+        let bins = bins(&[120, 125, 180, 340], 50);
+        assert_eq!(bins.into_iter().collect::<Vec<_>>(), vec![(100, 2), (150, 1), (300, 1)]);
+    }
+
+    #[test]
+    fn bins_of_millisecond_ticks() {
+        let ms = 1_000_000;
+        let bins = bins(&[3 * ms, 3 * ms + 400_000, 4 * ms, 9 * ms], ms);
+        assert_eq!(bins.into_iter().collect::<Vec<_>>(), vec![(3 * ms, 2), (4 * ms, 1), (9 * ms, 1)]);
+    }
+}
@@ -1,149 +1,1560 @@
 use structopt::StructOpt;
-use std::process::{Command, Stdio};
-use std::time::Instant;
-use std::collections::HashMap;
+use structopt::clap::AppSettings;
+use std::env;
+use std::ffi::{CString, OsString};
+use std::time::{Duration, Instant, SystemTime};
+use std::thread;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::process::{self, Command, ExitStatus, Stdio};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use threadpool::ThreadPool;
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, RecvTimeoutError};
 
-#[derive(StructOpt, PartialEq, Debug)]
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod exit;
+mod export;
+mod random;
+mod format;
+mod git;
+mod histogram;
+mod progress;
+mod remote;
+mod run;
+mod stats;
+mod system;
+
+use exit::{exit_with, Failure};
+use export::HyperfineResult;
+use format::{fill_template, print_summary, rfc3339, NumberFormat, TimeFormat, NANOS_PER_MS};
+use histogram::HistogramSort;
+use random::Rng;
+use progress::Progress;
+use remote::Remote;
+use run::{run_command, terminate_running, ResourceUsage, RunOptions, RunResult};
+use stats::{mean, percentile, welch_t_test, P2Quantile, PercentileMethod, Totals};
+
+/// Below this many runs percentiles are not reported, only the raw samples.
+const SMALL_SAMPLE: usize = 20;
+/// How often a `--max-load` throttled dispatcher checks again.
+const THROTTLE_POLL: Duration = Duration::from_millis(10);
+/// How often the collected results are checked for a SIGINT or SIGTERM.
+const INTERRUPT_POLL: Duration = Duration::from_millis(100);
+/// How long the runs in progress get to exit after an interrupt before
+/// they are given up on.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(1);
+/// Runs of the empty command timed by `--calibrate`.
+const CALIBRATION_RUNS: usize = 10;
+/// Named sets of options for `--preset`: name, description and arguments.
+const PRESETS: &[(&str, &str, &[&str])] = &[
+    ("quick", "a fast sanity check", &["-r", "5"]),
+    ("thorough", "many runs after a long warmup", &["-r", "1000", "--warmup-until-stable", "--max-warmup", "50", "--percentile-table"]),
+    ("ci", "a stable measurement that fails when noisy", &["-r", "30", "--warmup-until-stable", "--max-rsd", "10"]),
+];
+/// Placeholders available to `--format-template`.
+const TEMPLATE_FIELDS: &[&str] = &["n", "mean", "stddev", "min", "max", "p50", "p95", "p99", "total", "unit"];
+/// Most worker threads started, whatever `-c` asks for.
+const MAX_CONCURRENCY: u32 = 4096;
+/// First-run slowdown beyond which `--warmup-advice` suggests a warmup.
+const WARMUP_ADVICE_RATIO: f64 = 1.2;
+/// The latency profile printed by `--percentile-table`.
+const PERCENTILE_TABLE: &[(&str, f64)] = &[
+    ("p10", 0.10), ("p25", 0.25), ("p50", 0.50), ("p75", 0.75),
+    ("p90", 0.90), ("p95", 0.95), ("p99", 0.99), ("p99.9", 0.999),
+];
+
+#[derive(StructOpt, Clone, PartialEq, Debug)]
 struct Opt {
-    #[structopt(short, required=false, default_value="1", help="Number of times to run a command")]
+    #[structopt(short, required=false, default_value="1", env="RAVGTIME_REPETITIONS", help="Number of times to run a command")]
 	repetitions: u32,
-	#[structopt(short, required=false, default_value="1", help="Number of concurrent executions")]
+	#[structopt(short, required=false, default_value="1", env="RAVGTIME_CONCURRENCY", help="Number of concurrent executions")]
 	concurrency: u32,
     #[structopt(short, help="Pipe command stdout and stderr to /dev/null")]
 	quiet: bool,
+    #[structopt(long, help="Pipe command stdout to /dev/null")]
+    quiet_stdout: bool,
+    #[structopt(long, help="Pipe command stderr to /dev/null")]
+    quiet_stderr: bool,
     #[structopt(short, help="Display a histogram")]
 	histogram: bool,
+    #[structopt(long, conflicts_with_all=&["repeat-until-fail", "from-csv"], help="Report the first run separately as the cold start and compute the statistics from the rest")]
+    report_cold: bool,
+    #[structopt(long, requires="histogram", help="Mark the histogram bins holding p50, p95 and p99")]
+    annotate_percentiles: bool,
+    #[structopt(long, default_value="time", possible_values=HistogramSort::NAMES, help="Order the histogram rows by ascending time or by descending count")]
+    histogram_sort: HistogramSort,
+    #[structopt(long, help="Report how many runs took longer than this multiple of the mean")]
+    slow_threshold: Option<f64>,
+    #[structopt(long, default_value="3", help="Number of decimal places in reported times")]
+    precision: usize,
+    #[structopt(long, default_value="plain", possible_values=NumberFormat::NAMES, help="Whether to group thousands in reported times")]
+    number_format: NumberFormat,
+    #[structopt(long, help="Print the summary in two columns")]
+    compact: bool,
+    #[structopt(long, help="Print a table of common percentiles from p10 to p99.9")]
+    percentile_table: bool,
+    #[structopt(long, parse(from_os_str), help="Write the results to this file in hyperfine's JSON export format")]
+    export_hyperfine: Option<PathBuf>,
+    #[structopt(long, parse(from_os_str), help="Add a row with the aggregate results to the runs table of this SQLite database, using the sqlite3 tool")]
+    sqlite: Option<PathBuf>,
+    #[structopt(long, help="Name of the results in --sqlite and --export-hyperfine [default: the command, or the entry with --batch and --git-bench]")]
+    label: Option<String>,
+    #[structopt(long, parse(from_os_str), help="Write the sorted run times with their cumulative fraction to this file for plotting")]
+    plot_data: Option<PathBuf>,
+    #[structopt(long, help="Run discarded warmup iterations until consecutive times stabilise")]
+    warmup_until_stable: bool,
+    #[structopt(long, default_value="5", help="Maximum difference in percent between consecutive warmup runs considered stable")]
+    stable_within: f64,
+    #[structopt(long, default_value="20", help="Maximum number of warmup runs")]
+    max_warmup: u32,
+    #[structopt(long, default_value="midpoint", possible_values=PercentileMethod::NAMES, env="RAVGTIME_PERCENTILE_METHOD", help="How percentiles are picked from the samples")]
+    percentile_method: PercentileMethod,
+    #[structopt(long, help="Run the command on this host over ssh")]
+    remote: Option<String>,
+    #[structopt(long, help="Run the command directly instead of through sh -c")]
+    no_shell: bool,
+    #[structopt(long, help="Prefix every run with this command, e.g. \"taskset -c 0\" or \"nice -n 10\"")]
+    wrapper: Option<String>,
+    #[structopt(long, help="Hold back new runs while the 1-minute load average is above this value (Linux)")]
+    max_load: Option<f64>,
+    #[structopt(long, help="Refuse to start if the 1-minute load average is above this value (Linux)")]
+    require_idle: Option<f64>,
+    #[structopt(long, help="Keep running until the command fails, then show its output")]
+    repeat_until_fail: bool,
+    #[structopt(long, parse(try_from_str = parse_clock_time), help="Keep starting runs until this local time (HH:MM), today or tomorrow if it has passed")]
+    until_time: Option<(u32, u32)>,
+    #[structopt(long, conflicts_with_all=&["repeat-until-fail", "until-time"], help="Ignore -r and keep running until this many runs have succeeded, timing only those")]
+    successful_runs: Option<usize>,
+    #[structopt(long, requires="count-for", conflicts_with_all=&[
+        "repeat-until-fail", "until-time", "successful-runs", "batch", "git-bench", "from-csv",
+        "calibrate", "measure-output-size", "resource-stats", "histogram", "percentile-table", "report-cold",
+        "timestamps", "tick-hook", "stats-command", "export-hyperfine", "plot-data", "sqlite",
+        "warmup-until-stable", "stable-within", "max-warmup", "warmup-advice", "ramp", "max-runs",
+        "histogram-sort", "slow-threshold", "precision", "number-format", "compact", "label", "bin-width",
+        "percentile-method", "sample", "approx-percentiles", "record-env", "format-template", "robust-stats",
+        "progress-fd", "drift", "drift-threshold", "max-rsd", "compare-unpaired",
+    ], help="Only count the runs that complete within --for and report the rate, without timing each run")]
+    count_only: bool,
+    #[structopt(long="for", value_name="duration", requires="count-only", parse(try_from_str = parse_duration), help="How long --count-only keeps starting runs, e.g. 10s or 2m")]
+    count_for: Option<Duration>,
+    #[structopt(long, help="Maximum number of runs for --repeat-until-fail, --until-time and --successful-runs [default: unlimited]")]
+    max_runs: Option<u32>,
+    #[structopt(long, help="Refuse to start while another avgtime --exclusive benchmark is running on this machine")]
+    exclusive: bool,
+    #[structopt(long, help="Like --exclusive, but wait for the other benchmark to finish instead of refusing")]
+    exclusive_wait: bool,
+    #[structopt(long, help="Report the measured resolution of the clock used for timing")]
+    clock_info: bool,
+    #[structopt(long, help="Capture stderr together with stdout, preserving their order")]
+    merge_output: bool,
+    #[structopt(long, default_value="1048576", help="Keep at most this many bytes of captured output per stream, dropping the oldest")]
+    max_capture: usize,
+    #[structopt(long, help="Report mean context switches and page faults per run (Unix)")]
+    resource_stats: bool,
+    #[structopt(long, parse(try_from_str = parse_bin_width), help="Use histogram bins of this many milliseconds")]
+    bin_width: Option<f64>,
+    #[structopt(long, help="Run the command in a cgroup limited to these CPUs, e.g. 0-3 (Linux, cgroup v2)")]
+    cgroup_cpus: Option<String>,
+    #[structopt(long, help="Run the command in a cgroup limited to this many bytes of memory (Linux, cgroup v2)")]
+    cgroup_memory: Option<u64>,
+    #[structopt(long, help="Treat every argument as a separate command; a repetition runs them all in parallel")]
+    group: bool,
+    #[structopt(long, help="Measure the cost of starting an empty command and subtract it from every run")]
+    calibrate: bool,
+    #[structopt(long, help="Keep only a random sample of this many run times for percentiles, the histogram and exports; the summary stays exact")]
+    sample: Option<usize>,
+    #[structopt(long, help="Estimate percentiles over every run in constant memory instead of from the stored run times")]
+    approx_percentiles: bool,
+    #[structopt(long, help="Finish the report with the host, CPU, core count, OS and avgtime version")]
+    record_env: bool,
+    #[structopt(long, help="Run this shell command after every run, with {time} replaced by its duration in ms")]
+    tick_hook: Option<String>,
+    #[structopt(long, help="After the report, pipe the run times in milliseconds, one per line, to this shell command and show its output")]
+    stats_command: Option<String>,
+    #[structopt(long, help="Print the summary using this template, e.g. \"{mean}{unit} ± {stddev}{unit} (n={n})\"")]
+    format_template: Option<String>,
+    #[structopt(long, allow_hyphen_values=true, help="Start the command with this nice value, from -20 (highest priority) to 19 (Unix)")]
+    child_nice: Option<i32>,
+    #[structopt(long, help="Report how many runs fall within 1 and 2 standard deviations of the mean")]
+    robust_stats: bool,
+    #[structopt(long, help="Start from a named set of options, see --list-presets; options given on the command line take precedence")]
+    preset: Option<String>,
+    #[structopt(long, help="Count the bytes the command writes to stdout instead of showing them, and report size and throughput")]
+    measure_output_size: bool,
+    #[structopt(long, help="Write run started and completed events as JSON lines to this open file descriptor (Unix)")]
+    progress_fd: Option<i32>,
+    #[structopt(long, help="Record when each run started, shown for the min and max runs and added to --export-hyperfine")]
+    timestamps: bool,
+    #[structopt(long, help="List the presets available to --preset and exit")]
+    list_presets: bool,
+    #[structopt(long, requires="warmup-until-stable", help="Run this command for the warmup instead of the measured one")]
+    warmup_command: Option<String>,
+    #[structopt(long, help="Compare the first run with the rest to tell whether a warmup would help")]
+    warmup_advice: bool,
+    #[structopt(long, parse(try_from_str = parse_duration), help="Spread the start of the concurrent workers over this period, e.g. 500ms or 2s")]
+    ramp: Option<Duration>,
+    #[structopt(long, help="Compare the first and second half of the runs to detect drift")]
+    drift: bool,
+    #[structopt(long, default_value="10", help="Warn when the second half is slower by more than this percentage")]
+    drift_threshold: f64,
+    #[structopt(long, env="RAVGTIME_MAX_RSD", help="Fail if the relative standard deviation exceeds this percentage")]
+    max_rsd: Option<f64>,
+    #[structopt(long, help="After the command exits, keep the run going until this shell command succeeds")]
+    wait_for: Option<String>,
+    #[structopt(long, default_value="30s", parse(try_from_str = parse_duration), help="Fail a run whose --wait-for probe has not succeeded within this time")]
+    wait_for_timeout: Duration,
+    #[structopt(long, help="Replace {rand} in the command with a fresh random string of this length every run")]
+    random_arg: Option<usize>,
+    #[structopt(long, help="Seed for --random-arg, for reproducible inputs")]
+    seed: Option<u64>,
+    #[structopt(long, help="Skip running a command and report on the durations (in ms) in the last column of this CSV file")]
+    from_csv: Option<PathBuf>,
+    #[structopt(long, conflicts_with_all=&["from-csv", "plot-data"], help="Run every benchmark listed in this file (label,repetitions,command per line) and rank them")]
+    batch: Option<PathBuf>,
+    #[structopt(long, requires="batch", help="Show --batch ratios relative to this entry (label or 1-based position) instead of the fastest")]
+    baseline_command: Option<String>,
+    #[structopt(long, number_of_values=3, value_names=&["ref1", "ref2", "build-cmd"], conflicts_with_all=&["batch", "from-csv", "plot-data"], help="Check out and build each git revision, benchmark the command at both and compare them")]
+    git_bench: Vec<String>,
+    #[structopt(long, help="With --batch or --git-bench, test whether each entry differs significantly from the baseline")]
+    compare_unpaired: bool,
     #[structopt(subcommand, help="Command to run")]
-    command: Subcommands,
+    command: Option<Subcommands>,
 }
 
-#[derive(StructOpt, PartialEq, Debug)]
+#[derive(StructOpt, Clone, PartialEq, Debug)]
 enum Subcommands {
     #[structopt(external_subcommand)]
     Other(Vec<String>),
 }
 
 fn main() {
-    let opt = Opt::from_args();
-    let Subcommands::Other(cmd) = opt.command;
-	let mut ticks = Vec::new();
-	let pool = ThreadPool::new(opt.concurrency as usize);
+    let opt = parse_args();
+    warn_if_root(&opt);
+    if opt.clock_info {
+        let granularity = system::clock_granularity();
+        println!("Clock granularity: {}ns", granularity.as_nanos());
+        let display_step = 10f64.powi(6 - opt.precision as i32);
+        if granularity.as_nanos() as f64 > display_step {
+            eprintln!("avgtime: warning: the clock is coarser than the {} decimal places requested with --precision", opt.precision);
+        }
+    }
+    // Held until avgtime exits.
+    let _lock = if (opt.exclusive || opt.exclusive_wait) && opt.from_csv.is_none() {
+        take_exclusive_lock(opt.exclusive_wait)
+    } else {
+        None
+    };
+    if let Some(path) = &opt.batch {
+        run_batch(path, &opt);
+        return;
+    }
+    if !opt.git_bench.is_empty() {
+        run_git_bench(&opt);
+        return;
+    }
+    if let Some(window) = opt.count_for {
+        count_runs(&opt, window);
+        return;
+    }
+    let measurements = match &opt.from_csv {
+        Some(path) => load_csv(path, &opt),
+        None => benchmark(&opt),
+    };
+    report(&opt, measurements, &mut Vec::new());
+}
+
+/// Prints the results of a benchmark and applies the requested checks,
+/// returning the totals over the timed runs. Under --export-hyperfine the
+/// results are added to `exported`, the earlier benchmarks of this
+/// invocation, and all of them are written out.
+fn report(opt: &Opt, measurements: Measurements, exported: &mut Vec<HyperfineResult>) -> Totals {
+    let Measurements {
+        label, dispatched, completed, warmup, overhead, mut runs, totals, raw_sum, estimators, per_worker,
+        usage_total, usage_count, exit_failures, signals, timestamps, output_sizes, cold, first_failure,
+    } = measurements;
+
+    let ticks = order_runs(&mut runs);
+    let (min, min_run) = totals.min.unwrap();
+    let (max, max_run) = totals.max.unwrap();
+    let sum = totals.sum;
+    let avg = totals.mean();
+    let raw_avg = raw_sum as f64 / totals.count as f64;
+    let std_dev = totals.std_dev();
+    let time = TimeFormat { precision: opt.precision, numbers: opt.number_format };
+    let fmt = |nanos: f64| time.ms(nanos);
+    let pct = |p: f64| match estimators.iter().find(|(q, _)| *q == p) {
+        Some((_, estimator)) => estimator.estimate(),
+        None => percentile(&ticks, p, opt.percentile_method),
+    };
+    // The estimators see every run, while `ticks` may only be a sample.
+    let few_runs = if estimators.is_empty() { ticks.len() } else { totals.count } < SMALL_SAMPLE;
+
+    let mut summary = Vec::new();
+    summary.push(("Total time", fmt((sum + cold.unwrap_or(0)) as f64)));
+    summary.push(("Repetitions", dispatched.to_string()));
+    summary.push(("Completed", format!("{} / {} requested", completed, dispatched)));
+    if let Some((runs, stable)) = warmup {
+        summary.push(("Warmup runs", format!("{} ({})", runs, if stable { "stable" } else { "not stable" })));
+    }
+    if let Some(cold) = cold {
+        summary.push(("Cold start", format!("{} ({:.2}x the warm average)", fmt(cold as f64), cold as f64 / avg.max(1.0))));
+        summary.push(("Warm runs", totals.count.to_string()));
+    }
+    summary.push(("Average time", fmt(avg)));
+    if let Some(overhead) = overhead {
+        summary.push(("Raw average time", fmt(raw_avg)));
+        summary.push(("Subtracted overhead", fmt(overhead as f64)));
+    }
+    let started = |run: u32| match timestamps.as_ref().and_then(|started| started.get(&run)) {
+        Some(time) => format!(", started {}", rfc3339(*time)),
+        None => String::new(),
+    };
+    summary.push(("Min", format!("{} (run #{}{})", fmt(min as f64), min_run, started(min_run))));
+    summary.push(("Max", format!("{} (run #{}{})", fmt(max as f64), max_run, started(max_run))));
+    summary.push(("Range", format!("{} (max/min = {:.2}x)", fmt((max - min) as f64), max as f64 / min.max(1) as f64)));
+    summary.push(("Standard deviation", fmt(std_dev)));
+
+    // With this few samples p95/p99 are just the max in disguise, so show
+    // what was actually observed instead of pretending to have percentiles.
+    if few_runs {
+        summary.push(("Observed range", format!("{} - {}", fmt(min as f64), fmt(max as f64))));
+        let observed: Vec<String> = ticks.iter().map(|t| fmt(*t as f64)).collect();
+        summary.push(("Observed runs", observed.join(", ")));
+    } else {
+        if opt.approx_percentiles {
+            summary.push(("Percentiles", "approximate (P² estimate)".to_string()));
+        }
+        summary.push(("p95", fmt(pct(0.95))));
+        summary.push(("p99", fmt(pct(0.99))));
+    }
+    match &opt.format_template {
+        Some(template) => {
+            // The same false precision as above, so percentiles stay unfilled.
+            let percentile = |q| if few_runs { "n/a".to_string() } else { time.number(pct(q)) };
+            let values: Vec<(&str, String)> = TEMPLATE_FIELDS.iter().map(|name| (*name, match *name {
+                "n" => totals.count.to_string(),
+                "mean" => time.number(avg),
+                "stddev" => time.number(std_dev),
+                "min" => time.number(min as f64),
+                "max" => time.number(max as f64),
+                "p50" => percentile(0.50),
+                "p95" => percentile(0.95),
+                "p99" => percentile(0.99),
+                "total" => time.number(sum as f64),
+                "unit" => "ms".to_string(),
+                _ => unreachable!(),
+            })).collect();
+            println!("{}", fill_template(&unescape(template), &values).unwrap_or_else(|e| exit_with(Failure::Usage, e)));
+        }
+        None => print_summary(&summary, opt.compact),
+    }
+    warn_if_resolution_too_coarse(&ticks, &time);
+
+    if opt.percentile_table && few_runs {
+        // Same rule as for p95/p99 in the summary.
+        eprintln!("avgtime: warning: the percentile table needs at least {} runs, skipping it", SMALL_SAMPLE);
+    } else if opt.percentile_table {
+        let nanos: Vec<u128> = PERCENTILE_TABLE.iter()
+            .map(|(_, p)| pct(*p).round() as u128)
+            .collect();
+        let values: Vec<String> = nanos.iter().map(|n| fmt(*n as f64)).collect();
+        let width = values.iter().map(|value| value.len()).max().unwrap();
+        // The table ends with p99.9, which every bar is scaled to.
+        let longest = *nanos.last().unwrap();
+        println!("Percentiles{}:", if opt.approx_percentiles { " (approximate)" } else { "" });
+        for (((label, _), value), n) in PERCENTILE_TABLE.iter().zip(&values).zip(&nanos) {
+            println!("  {:<7}{:>width$}  {}", label, value, histogram::bar(*n, longest), width = width);
+        }
+    }
+
+    if let Some(factor) = opt.slow_threshold {
+        let limit = factor * avg;
+        let slow = ticks.iter().filter(|t| **t as f64 > limit).count();
+        println!("{} runs were >{}x the mean", slow, factor);
+    }
+
+    if opt.robust_stats {
+        let within = |deviations: f64| {
+            let inside = ticks.iter().filter(|t| (**t as f64 - avg).abs() <= deviations * std_dev).count();
+            inside as f64 / ticks.len() as f64 * 100.0
+        };
+        println!("Within 1 std dev of the mean: {:.1}% of runs (normal: 68.3%)", within(1.0));
+        println!("Within 2 std devs of the mean: {:.1}% of runs (normal: 95.4%)", within(2.0));
+    }
+
+    if opt.warmup_advice && runs.len() >= 2 {
+        let durations: Vec<u128> = runs.iter().map(|(_, elapsed)| *elapsed).collect();
+        let penalty = durations[0] as f64 / mean(&durations[1..]).max(1.0);
+        println!("First run was {:.1}x the steady-state mean", penalty);
+        if penalty > WARMUP_ADVICE_RATIO {
+            println!("Consider discarding warmup runs with --warmup-until-stable");
+        }
+    }
+
+    if opt.drift && runs.len() >= 2 {
+        let durations: Vec<u128> = runs.iter().map(|(_, elapsed)| *elapsed).collect();
+        let (first, second) = durations.split_at(durations.len() / 2);
+        let difference = mean(second) - mean(first);
+        let percent = difference / mean(first).max(1.0) * 100.0;
+        println!("Drift: second half {}{} ({:+.1}%) vs first half", if difference < 0.0 { "-" } else { "+" }, fmt(difference.abs()), percent);
+        if percent > opt.drift_threshold {
+            eprintln!("avgtime: warning: later runs were {:.1}% slower, possibly due to thermal throttling or a resource leak", percent);
+        }
+    }
+
+    if opt.concurrency > 1 && !per_worker.is_empty() {
+        println!("Per-worker runs:");
+        for (worker, totals) in &per_worker {
+            println!("  worker {}: {} runs, mean {}", worker, totals.count, fmt(totals.mean()));
+        }
+    }
+
+    if exit_failures > 0 {
+        println!("{} runs exited with a non-zero status", exit_failures);
+    }
+    for (signal, count) in &signals {
+        println!("{} runs killed by signal {}", count, signal);
+    }
+
+    if opt.resource_stats {
+        if let Some(total) = usage_total {
+            let mean_of = |counter: fn(&ResourceUsage) -> i64| counter(&total) as f64 / usage_count as f64;
+            println!("Context switches per run: {:.1} voluntary, {:.1} involuntary",
+                mean_of(|u| u.voluntary_switches), mean_of(|u| u.involuntary_switches));
+            println!("Page faults per run: {:.1} major, {:.1} minor",
+                mean_of(|u| u.major_faults), mean_of(|u| u.minor_faults));
+        } else {
+            eprintln!("avgtime: warning: resource usage is not available on this platform");
+        }
+    }
+
+    if opt.measure_output_size && output_sizes.count > 0 {
+        let (min, _) = output_sizes.min.unwrap();
+        let (max, _) = output_sizes.max.unwrap();
+        println!("Output size: {:.0} bytes mean, {} min, {} max, {:.0} std dev",
+            output_sizes.mean(), min, max, output_sizes.std_dev());
+        let ms = sum as f64 / NANOS_PER_MS as f64;
+        println!("Throughput: {:.1} bytes/ms", output_sizes.sum as f64 / ms.max(f64::MIN_POSITIVE));
+    }
+
+    if opt.repeat_until_fail {
+        match &first_failure {
+            Some((x, successes, result)) => {
+                println!("Run #{} failed ({}) after {} successful runs", x, result.status, successes);
+                println!("Failing output:");
+                io::stdout().write_all(&result.stdout).and_then(|_| io::stdout().write_all(&result.stderr))
+                    .expect("Could not write failing output");
+            }
+            None => println!("No failures in {} runs", totals.count),
+        }
+    }
+
+    if opt.histogram {
+        // Only percentiles that the summary reports are marked.
+        let markers: Vec<(&str, f64)> = if opt.annotate_percentiles && !few_runs {
+            [("p50", 0.50), ("p95", 0.95), ("p99", 0.99)].iter().map(|(label, p)| (*label, pct(*p))).collect()
+        } else {
+            Vec::new()
+        };
+        let bin_width = opt.bin_width.map(|ms| (ms * NANOS_PER_MS as f64).round() as u128);
+        histogram::print(&ticks, &time, bin_width, &markers, opt.histogram_sort);
+    }
+
+    if opt.record_env {
+        let unknown = || "unknown".to_string();
+        println!("Environment:");
+        println!("  Host: {}", system::hostname().unwrap_or_else(unknown));
+        println!("  CPU: {}", system::cpu_model().unwrap_or_else(unknown));
+        println!("  Cores: {}", thread::available_parallelism().map(|n| n.to_string()).unwrap_or_else(|_| unknown()));
+        println!("  OS: {}", system::os_description());
+        println!("  avgtime: {}", env!("CARGO_PKG_VERSION"));
+    }
+
+    if let Some(command) = &opt.stats_command {
+        run_stats_command(command, &runs);
+    }
+
+    if let Some(path) = &opt.plot_data {
+        export::write_plot_data(path, &label, &ticks, &summary)
+            .unwrap_or_else(|e| exit_with(Failure::Usage, format!("could not write {}: {}", path.display(), e)));
+    }
+
+    if let Some(path) = &opt.export_hyperfine {
+        exported.push(HyperfineResult {
+            // Like hyperfine's --command-name.
+            command: opt.label.clone().unwrap_or_else(|| label.clone()),
+            times: runs.iter().map(|(_, elapsed)| *elapsed).collect(),
+            ticks: ticks.clone(),
+            timestamps: timestamps.as_ref().map(|started| runs.iter().map(|(x, _)| rfc3339(started[x])).collect()),
+            mean: avg,
+            std_dev,
+        });
+        export::write_hyperfine(path, exported)
+            .unwrap_or_else(|e| exit_with(Failure::Usage, format!("could not write {}: {}", path.display(), e)));
+    }
+
+    if let Some(path) = &opt.sqlite {
+        let stats = [("total", sum as f64), ("mean", avg), ("stddev", std_dev), ("min", min as f64), ("max", max as f64)];
+        // Too few runs for percentiles, as in the summary.
+        let percentile = |q| Some(pct(q)).filter(|_| !few_runs);
+        let percentiles = [("p50", percentile(0.50)), ("p95", percentile(0.95)), ("p99", percentile(0.99))];
+        let name = opt.label.as_deref().unwrap_or(&label);
+        export::write_sqlite(path, &rfc3339(SystemTime::now()), name, &label, totals.count, &stats, &percentiles)
+            .unwrap_or_else(|e| exit_with(Failure::Usage, format!("could not write {}: {}", path.display(), e)));
+    }
+
+    if let Some(max_rsd) = opt.max_rsd {
+        let rsd = std_dev / avg.max(1.0) * 100.0;
+        if rsd > max_rsd {
+            exit_with(Failure::Assertion, format!(
+                "relative standard deviation {:.1}% exceeds --max-rsd {}%, rerun on a quieter machine",
+                rsd, max_rsd));
+        }
+    }
+
+    if opt.repeat_until_fail && first_failure.is_some() {
+        exit_with(Failure::Command, "the command failed");
+    }
+    totals
+}
+
+/// Puts the stored runs back in dispatch order, whatever order they
+/// finished in, and returns their durations sorted (the ticks).
+fn order_runs(runs: &mut [(u32, u128)]) -> Vec<u128> {
+    runs.sort();
+    let mut ticks: Vec<u128> = runs.iter().map(|(_, elapsed)| *elapsed).collect();
+    ticks.sort();
+    ticks
+}
+
+/// Everything collected while benchmarking, before it is reported.
+struct Measurements {
+    label: String,
+    dispatched: usize,
+    /// Runs that finished, whether or not they were timed.
+    completed: usize,
+    warmup: Option<(u32, bool)>,
+    overhead: Option<u128>,
+    /// (dispatch index, duration) of the stored runs.
+    runs: Vec<(u32, u128)>,
+    totals: Totals,
+    raw_sum: u128,
+    estimators: Vec<(f64, P2Quantile)>,
+    per_worker: BTreeMap<usize, Totals>,
+    usage_total: Option<ResourceUsage>,
+    usage_count: usize,
+    exit_failures: usize,
+    signals: BTreeMap<i32, usize>,
+    /// Start time of each stored run, under --timestamps.
+    timestamps: Option<BTreeMap<u32, SystemTime>>,
+    /// Bytes each run wrote to stdout, under --measure-output-size.
+    output_sizes: Totals,
+    /// Duration of the first run under --report-cold, kept out of the totals.
+    cold: Option<u128>,
+    /// Index, preceding successful runs and result of the first failed run.
+    first_failure: Option<(u32, usize, RunResult)>,
+}
+
+/// Keeps every worker running the command until `window` has passed and
+/// reports how many runs completed. Nothing is recorded per run, which
+/// keeps the overhead down for very fast commands.
+fn count_runs(opt: &Opt, window: Duration) {
+    let cmd = benchmarked_command(opt).unwrap_or_else(|| exit_with(Failure::Usage, "no command provided"));
+    require_idle(opt);
+    let remote = connect_remote(opt);
+    let launcher = launcher(opt, remote.as_ref());
+    let (_, cmds) = repetition_commands(opt, cmd, &launcher, remote.is_some());
+    let cgroup = create_cgroup(opt);
+    let options = run_options(opt, cgroup_procs(&cgroup));
+    let placeholders = Arc::new(Mutex::new(Placeholders::new(opt)));
+    let max_load = usable_max_load(opt);
+    let (cmds, options) = (Arc::new(cmds), Arc::new(options));
+    let pool = create_pool(opt.concurrency);
+    let started = Arc::new(AtomicUsize::new(0));
+    let completed = Arc::new(AtomicUsize::new(0));
+    let failed = Arc::new(AtomicUsize::new(0));
+    system::catch_interrupts();
+    let start = Instant::now();
+    let deadline = start + window;
+    for _ in 0..pool.max_count() {
+        let (cmds, options, placeholders) = (cmds.clone(), options.clone(), placeholders.clone());
+        let (started, completed, failed) = (started.clone(), completed.clone(), failed.clone());
+        pool.execute(move || loop {
+            wait_for_load(max_load);
+            if Instant::now() >= deadline || system::interrupted() {
+                break;
+            }
+            let index = started.fetch_add(1, Ordering::Relaxed) as u32;
+            let cmds = placeholders.lock().unwrap().expand(&cmds, index);
+            let result = run_command(&cmds, &options)
+                .unwrap_or_else(|e| exit_with(Failure::Command, format!("failed to execute process: {}", e)));
+            completed.fetch_add(1, Ordering::Relaxed);
+            if !result.status.success() {
+                failed.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+    pool.join();
+    // The last runs end after the deadline, so the rate uses the time they
+    // actually took.
+    let elapsed = start.elapsed();
+    let completed = completed.load(Ordering::Relaxed);
+    println!("Completed: {} runs in {:.3}s", completed, elapsed.as_secs_f64());
+    let failed = failed.load(Ordering::Relaxed);
+    if failed > 0 {
+        println!("{} runs did not succeed", failed);
+    }
+    println!("Throughput: {:.2} runs/s", completed as f64 / elapsed.as_secs_f64());
+}
+
+/// Exits with `Failure::Busy` if the load average is above --require-idle.
+fn require_idle(opt: &Opt) {
+    if let Some(max_load) = opt.require_idle {
+        match system::load_average() {
+            Some(load) if load > max_load => exit_with(Failure::Busy, format!(
+                "the machine is too busy: load average {:.2} is above --require-idle {}", load, max_load)),
+            Some(_) => {}
+            None => eprintln!("avgtime: warning: load average is not available, ignoring --require-idle"),
+        }
+    }
+}
+
+fn connect_remote(opt: &Opt) -> Option<Remote> {
+    opt.remote.as_ref().map(|host| {
+        Remote::connect(host).unwrap_or_else(|e| exit_with(Failure::Command, e))
+    })
+}
+
+/// What goes in front of every command: ssh for --remote, the --wrapper
+/// and, unless --no-shell, `sh -c`.
+fn launcher(opt: &Opt, remote: Option<&Remote>) -> Vec<String> {
+    let wrapper: Vec<String> = opt.wrapper.iter().flat_map(|w| w.split_whitespace()).map(String::from).collect();
+    match remote {
+        Some(remote) => [remote.command_prefix(), wrapper].concat(),
+        None if opt.no_shell => wrapper,
+        None => [wrapper, vec!["sh".to_string(), "-c".to_string()]].concat(),
+    }
+}
+
+/// A single command line given as one string, split into words when it
+/// is run directly with --no-shell.
+fn shell_command(opt: &Opt, launcher: &[String], remote: bool, command: &str) -> Vec<String> {
+    if opt.no_shell && !remote {
+        [launcher.to_vec(), command.split_whitespace().map(String::from).collect()].concat()
+    } else {
+        [launcher.to_vec(), vec![command.to_string()]].concat()
+    }
+}
+
+/// The label and the commands of one repetition: the command, or with
+/// --group every argument as its own command run in parallel.
+fn repetition_commands(opt: &Opt, cmd: &[String], launcher: &[String], remote: bool) -> (String, Vec<Vec<String>>) {
+    if opt.group {
+        let cmds = cmd.iter().map(|member| shell_command(opt, launcher, remote, member)).collect();
+        (cmd.join(" & "), cmds)
+    } else {
+        (cmd.join(" "), vec![[launcher, cmd].concat()])
+    }
+}
+
+/// Settings every run of the command shares.
+fn run_options(opt: &Opt, cgroup: Option<CString>) -> RunOptions {
+    RunOptions {
+        quiet_stdout: opt.quiet || opt.quiet_stdout,
+        quiet_stderr: opt.quiet || opt.quiet_stderr,
+        capture: opt.repeat_until_fail,
+        merge_output: opt.merge_output,
+        max_capture: opt.max_capture,
+        resource_stats: opt.resource_stats,
+        cgroup,
+        wait_for: opt.wait_for.clone(),
+        wait_for_timeout: opt.wait_for_timeout,
+        measure_output: opt.measure_output_size,
+        nice: opt.child_nice,
+    }
+}
+
+/// The --max-load limit, unless the load average cannot be read here.
+fn usable_max_load(opt: &Opt) -> Option<f64> {
+    opt.max_load.filter(|_| {
+        let available = system::load_average().is_some();
+        if !available {
+            eprintln!("avgtime: warning: load average is not available, ignoring --max-load");
+        }
+        available
+    })
+}
+
+/// Holds back the next run while the load average is above `max_load`.
+fn wait_for_load(max_load: Option<f64>) {
+    if let Some(max_load) = max_load {
+        while system::load_average().is_some_and(|load| load > max_load) && !system::interrupted() {
+            thread::sleep(THROTTLE_POLL);
+        }
+    }
+}
+
+/// The command line to benchmark, unless none or an empty one was given.
+fn benchmarked_command(opt: &Opt) -> Option<&Vec<String>> {
+    match &opt.command {
+        Some(Subcommands::Other(cmd)) if cmd.first().is_some_and(|program| !program.is_empty()) => {
+            // Without a shell every --group member is split into its own
+            // command line, which must not come out empty.
+            let blank_member = opt.group && opt.no_shell && cmd.iter().any(|member| member.trim().is_empty());
+            if blank_member { None } else { Some(cmd) }
+        }
+        _ => None,
+    }
+}
+
+/// Runs the command as configured and collects the results.
+fn benchmark(opt: &Opt) -> Measurements {
+    let cmd = benchmarked_command(opt).unwrap_or_else(|| exit_with(Failure::Usage, "no command provided"));
+    require_idle(opt);
+    let remote = connect_remote(opt);
+    let launcher = launcher(opt, remote.as_ref());
+    let empty_cmd = [launcher.clone(), vec!["true".to_string()]].concat();
+    let warmup_cmds = opt.warmup_command.as_ref().map(|warmup| vec![shell_command(opt, &launcher, remote.is_some(), warmup)]);
+    let (label, cmds) = repetition_commands(opt, cmd, &launcher, remote.is_some());
+    let cgroup = create_cgroup(opt);
+    let options = run_options(opt, cgroup_procs(&cgroup));
+    let seed = opt.seed;
+    let mut placeholders = Placeholders::new(opt);
+	let mut runs: Vec<(u32, u128)> = Vec::new();
+	let pool = create_pool(opt.concurrency);
+	let concurrency = pool.max_count() as u32;
 	let (tx, rx) = channel();
 
-    for _x in 0..opt.repetitions {
+    let warmup = if opt.warmup_until_stable {
+        let warmup_cmds = warmup_cmds.as_deref().unwrap_or(&cmds);
+        Some(warm_up_until_stable(warmup_cmds, &mut placeholders, &options, opt.stable_within, opt.max_warmup))
+    } else {
+        None
+    };
+    let overhead = if opt.calibrate {
+        Some(measure_overhead(&empty_cmd, &options))
+    } else {
+        None
+    };
+
+    let max_load = usable_max_load(opt);
+    let deadline = opt.until_time.map(|(hour, minute)| {
+        let left = system::until_local_time(hour, minute)
+            .unwrap_or_else(|| exit_with(Failure::Usage, "--until-time is not supported on this platform"));
+        Instant::now() + left
+    });
+    // Runs are handed out one at a time when the decision to start another
+    // depends on how the earlier ones went.
+    let paced = max_load.is_some() || opt.repeat_until_fail || deadline.is_some() || opt.successful_runs.is_some();
+    let limit = if opt.repeat_until_fail || deadline.is_some() || opt.successful_runs.is_some() {
+        opt.max_runs.unwrap_or(u32::MAX)
+    } else {
+        opt.repetitions
+    };
+    // Set by a worker when its run ends the benchmark early.
+    let stop = Arc::new(AtomicBool::new(false));
+    let progress = opt.progress_fd.map(|fd| {
+        Arc::new(Progress::open(fd).unwrap_or_else(|e| exit_with(Failure::Usage, format!("--progress-fd {}: {}", fd, e))))
+    });
+    system::catch_interrupts();
+    // Successful runs so far, for --successful-runs.
+    let succeeded = Arc::new(AtomicUsize::new(0));
+    let mut dispatched = 0;
+    for x in 0..limit {
+		if paced {
+			// Only hand out a run once a worker is free, so that the checks
+			// below apply to every run rather than to a full queue.
+			while pool.active_count() + pool.queued_count() >= concurrency as usize && !system::interrupted() {
+				thread::sleep(THROTTLE_POLL);
+			}
+		}
+		if let Some(target) = opt.successful_runs {
+			// Wait while the runs in flight could still make up the target.
+			let in_flight = || pool.active_count() + pool.queued_count();
+			while in_flight() > 0 && succeeded.load(Ordering::SeqCst) + in_flight() >= target && !system::interrupted() {
+				thread::sleep(THROTTLE_POLL);
+			}
+			if succeeded.load(Ordering::SeqCst) >= target {
+				break;
+			}
+		}
+		if stop.load(Ordering::SeqCst) || system::interrupted() {
+			break;
+		}
+		wait_for_load(max_load);
+		if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+			break;
+		}
+		dispatched += 1;
 		let tx = tx.clone();
-		let cmd = cmd.clone();
-		let quiet = opt.quiet.clone();
+		let stop = stop.clone();
+		let succeeded = succeeded.clone();
+		let progress = progress.clone();
+		let repeat_until_fail = opt.repeat_until_fail;
+		// The cold start is not timed with the rest, so it does not count
+		// towards --successful-runs either.
+		let counts_as_success = !(opt.report_cold && x == 0);
+		let cmds = placeholders.expand(&cmds, x);
+		let options = options.clone();
+		let delay = match opt.ramp {
+			Some(ramp) if x < concurrency => ramp * x / concurrency,
+			_ => Duration::from_secs(0),
+		};
 		pool.execute(move || {
-			let elapsed = run_command(&cmd, quiet);
-			tx.send(elapsed).expect("Could not send to channel");
+			thread::sleep(delay);
+			// Runs still queued when a stop is requested are dropped.
+			if system::interrupted() {
+				return;
+			}
+			if let Some(progress) = &progress {
+				progress.started(x);
+			}
+			let result = run_command(&cmds, &options);
+			if repeat_until_fail && result.as_ref().is_ok_and(|r| !r.status.success()) {
+				stop.store(true, Ordering::SeqCst);
+			}
+			if counts_as_success && result.as_ref().is_ok_and(|r| r.status.success()) {
+				succeeded.fetch_add(1, Ordering::SeqCst);
+			}
+			// Nobody is listening any more if the run was given up on after
+			// an interrupt.
+			let _ = tx.send((x, thread::current().id(), result));
 		})
 	}
 	
 	drop(tx);
-	for t in rx.iter() {
-		let elapsed = t;
-		ticks.push(elapsed);
+	let mut first_failure = None;
+	let mut usage_total: Option<ResourceUsage> = None;
+	let mut usage_count = 0;
+	let mut exit_failures = 0;
+	let mut signals: BTreeMap<i32, usize> = BTreeMap::new();
+	// Pool threads numbered in the order they first reported, with the
+	// totals of the runs each one handled.
+	let mut workers = HashMap::new();
+	let mut per_worker: BTreeMap<usize, Totals> = BTreeMap::new();
+	let mut totals = Totals::default();
+	let mut output_sizes = Totals::default();
+	let mut timestamps = if opt.timestamps { Some(BTreeMap::new()) } else { None };
+	let mut raw_sum = 0;
+	let mut estimators = percentile_estimators(opt);
+	let mut reservoir = opt.sample.map(|size| (size, seed.map(Rng::new).unwrap_or_else(Rng::from_time)));
+	let mut completed = 0;
+	let mut cold = None;
+	let mut cut_short = 0;
+	let mut given_up = None;
+	loop {
+		let (x, thread_id, t) = match rx.recv_timeout(INTERRUPT_POLL) {
+			Ok(received) => received,
+			Err(RecvTimeoutError::Disconnected) => break,
+			Err(RecvTimeoutError::Timeout) => {
+				match given_up {
+					None if system::interrupted() => {
+						// A SIGTERM sent to avgtime alone does not reach the
+						// commands, so stop them rather than wait for them.
+						terminate_running();
+						given_up = Some(Instant::now() + INTERRUPT_GRACE);
+					}
+					Some(given_up) if Instant::now() >= given_up => {
+						cut_short += pool.active_count();
+						break;
+					}
+					_ => {}
+				}
+				continue;
+			}
+		};
+		if system::interrupted() && !t.as_ref().is_ok_and(|r| r.status.code().is_some()) {
+			// Runs killed by the signal, from the terminal or from us, have
+			// no real time; those that still exited normally are kept.
+			cut_short += 1;
+			continue;
+		}
+		let result = t.unwrap_or_else(|e| exit_with(Failure::Command, format!("failed to execute process: {}", e)));
+		completed += 1;
+		if let Some(progress) = &progress {
+			progress.completed(x, result.elapsed, &result.status);
+		}
+		if !result.status.success() {
+			match killed_by(&result.status) {
+				Some(signal) => *signals.entry(signal).or_insert(0) += 1,
+				None => exit_failures += 1,
+			}
+		}
+		if opt.successful_runs.is_some() && !result.status.success() {
+			// Failed runs are counted above but not timed.
+			continue;
+		}
+		let elapsed = result.elapsed.saturating_sub(overhead.unwrap_or(0));
+		if opt.report_cold && x == 0 {
+			// The cold start is reported on its own, not as part of the stats.
+			cold = Some(elapsed);
+			continue;
+		}
+		raw_sum += result.elapsed;
+		totals.add(x, elapsed);
+		if let Some(hook) = &opt.tick_hook {
+			run_tick_hook(hook, x, elapsed, &result.status, opt.precision);
+		}
+		for (_, estimator) in &mut estimators {
+			estimator.add(elapsed);
+		}
+		// With --sample keep a uniform sample of the runs (Algorithm R).
+		let evicted = match &mut reservoir {
+			Some((size, rng)) if runs.len() >= *size => {
+				let slot = rng.below(totals.count as u64) as usize;
+				if slot < *size {
+					Some(std::mem::replace(&mut runs[slot], (x, elapsed)).0)
+				} else {
+					Some(x)
+				}
+			}
+			_ => {
+				runs.push((x, elapsed));
+				None
+			}
+		};
+		// Keep start times for the stored runs and for the min and max, which
+		// may not have been sampled.
+		if let Some(timestamps) = &mut timestamps {
+			timestamps.insert(x, result.started);
+			let extreme = |run| totals.min.is_some_and(|(_, min)| min == run) || totals.max.is_some_and(|(_, max)| max == run);
+			if let Some(run) = evicted.filter(|run| !extreme(*run)) {
+				timestamps.remove(&run);
+			}
+		}
+		let next = workers.len() + 1;
+		per_worker.entry(*workers.entry(thread_id).or_insert(next)).or_default().add(x, elapsed);
+		if let Some(bytes) = result.stdout_bytes {
+			output_sizes.add(x, u128::from(bytes));
+		}
+		if let Some(usage) = result.usage {
+			usage_total = Some(usage_total.map_or(usage, |total| total + usage));
+			usage_count += 1;
+		}
+		if first_failure.is_none() && !result.status.success() {
+			let successes = totals.count - 1;
+			first_failure = Some((x, successes, result));
+		}
 	}
+    if let Some(progress) = &progress {
+        progress.done(completed);
+    }
+    if totals.count == 0 {
+        exit_with(Failure::Command, if opt.successful_runs.is_some() {
+            "no runs succeeded"
+        } else if cold.is_some() {
+            "no runs completed after the cold start"
+        } else {
+            "no runs completed"
+        });
+    }
+    if system::interrupted() {
+        eprintln!("avgtime: interrupted, reporting the {} completed runs", completed);
+        if cut_short > 0 {
+            eprintln!("avgtime: discarded {} runs that were still in progress", cut_short);
+        }
+    } else if completed < dispatched {
+        eprintln!("avgtime: warning: only {} of {} dispatched runs completed", completed, dispatched);
+    }
 
-    ticks.sort();
+    Measurements {
+        label, dispatched, completed, warmup, overhead, runs, totals, raw_sum, estimators, per_worker,
+        usage_total, usage_count, exit_failures, signals, timestamps, output_sizes, cold, first_failure,
+    }
+}
 
-    let mut sum = 0;
-    let mut sum_square = 0;
-    for tick in &mut ticks {
-        sum += *tick;
-        sum_square += *tick * *tick;
-    }
-    let min = ticks.first();
-    let max = ticks.last();
-    let avg = sum / opt.repetitions as u128;
-    // Do I risk loosing some accuracy by casting to f64?
-    let std_dev = ((sum_square / opt.repetitions as u128 - avg * avg) as f32).sqrt();
-
-    let p95_index = 0.95 * opt.repetitions as f32 - 1.0;
-    let p99_index = 0.99 * opt.repetitions as f32 - 1.0;
-
-    let p95 = if p95_index == p95_index.round() {
-        let i1 = ticks[p95_index as usize];
-        let i2 = ticks[p95_index as usize + 1];
-        (i1 + i2) / 2
-    } else {
-        ticks[p95_index.ceil() as usize] as u128
-    };
-    let p99 = if p99_index == p99_index.round() {
-        let i1 = ticks[p99_index as usize];
-        let i2 = ticks[p99_index as usize + 1];
-        (i1 + i2) / 2
-    } else {
-        ticks[p99_index.ceil() as usize]
-    };
+/// Reads previously saved durations instead of running anything. Each
+/// line holds one run, with its duration in milliseconds in the last
+/// comma-separated column; a header line is skipped.
+fn load_csv(path: &Path, opt: &Opt) -> Measurements {
+    let read_error = |e: io::Error| exit_with(Failure::Usage, format!("could not read {}: {}", path.display(), e));
+    let contents = fs::read_to_string(path).unwrap_or_else(read_error);
+    let mut runs = Vec::new();
+    let mut totals = Totals::default();
+    let mut estimators = percentile_estimators(opt);
+    for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let field = line.rsplit(',').next().unwrap().trim().trim_matches('"');
+        let ms: f64 = match field.parse() {
+            Ok(ms) if ms >= 0.0 => ms,
+            Err(_) if number == 0 => continue,
+            _ => exit_with(Failure::Usage, format!("{}:{}: invalid duration: {}", path.display(), number + 1, field)),
+        };
+        let nanos = (ms * NANOS_PER_MS as f64).round() as u128;
+        let index = runs.len() as u32;
+        runs.push((index, nanos));
+        totals.add(index, nanos);
+        for (_, estimator) in &mut estimators {
+            estimator.add(nanos);
+        }
+    }
+    if runs.is_empty() {
+        exit_with(Failure::Usage, format!("no durations found in {}", path.display()));
+    }
+    Measurements {
+        label: path.display().to_string(),
+        dispatched: runs.len(),
+        completed: runs.len(),
+        warmup: None,
+        overhead: None,
+        raw_sum: totals.sum,
+        runs,
+        totals,
+        estimators,
+        per_worker: BTreeMap::new(),
+        usage_total: None,
+        usage_count: 0,
+        exit_failures: 0,
+        signals: BTreeMap::new(),
+        timestamps: None,
+        cold: None,
+        output_sizes: Totals::default(),
+        first_failure: None,
+    }
+}
 
-    println!("Total time: {}ms", sum);
-    println!("Repetitions: {}", opt.repetitions);
-    println!("Average time: {}ms", avg);
-    println!("Min: {}ms", min.unwrap());
-    println!("Max: {}ms", max.unwrap());
-    println!("Standard deviation: {}", std_dev);
-    println!("p95: {}ms", p95);
-    println!("p99: {}ms", p99);
+/// Runs every benchmark listed in a batch file in turn, then ranks them by
+/// mean time. Each line is `label,repetitions,command`, where an empty
+/// repetitions field means `-r`; blank lines and `#` comments are skipped.
+fn run_batch(path: &Path, opt: &Opt) {
+    let contents = fs::read_to_string(path)
+        .unwrap_or_else(|e| exit_with(Failure::Usage, format!("could not read {}: {}", path.display(), e)));
+    // Parse the whole file first so a typo fails before anything runs.
+    let mut entries = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| format!("{}:{}: {}", path.display(), number + 1, reason);
+        let fields: Vec<&str> = line.splitn(3, ',').map(str::trim).collect();
+        let (label, repetitions, command) = match fields[..] {
+            [label, repetitions, command] if !label.is_empty() && !command.is_empty() => (label, repetitions, command),
+            _ => exit_with(Failure::Usage, invalid("expected label,repetitions,command")),
+        };
+        let repetitions = match repetitions {
+            "" => opt.repetitions,
+            count => count.parse().ok().filter(|count| *count > 0)
+                .unwrap_or_else(|| exit_with(Failure::Usage, invalid("repetitions must be a positive number"))),
+        };
+        entries.push((label, repetitions, command));
+    }
+    if entries.is_empty() {
+        exit_with(Failure::Usage, format!("no benchmarks found in {}", path.display()));
+    }
+    // Label of the entry the ranking is relative to, given by label or
+    // 1-based position.
+    let baseline = opt.baseline_command.as_ref().map(|wanted| {
+        let index = entries.iter().position(|(label, _, _)| label == wanted)
+            .or_else(|| wanted.parse::<usize>().ok().filter(|n| (1..=entries.len()).contains(n)).map(|n| n - 1))
+            .unwrap_or_else(|| exit_with(Failure::Usage, format!("--baseline-command {} matches no entry in {}", wanted, path.display())));
+        entries[index].0
+    });
 
-    if opt.histogram {
-        let rounding_quotient = match *min.unwrap() {
-            0..=1_000 => 1,
-            1_001..=10_000 => 10,
-            10_001..=100_000 => 100,
-            100_001..=1_000_000 => 1000,
-            1_000_001..=std::u128::MAX => 10000,
+    let mut results = Vec::new();
+    let mut exported = Vec::new();
+    for (label, repetitions, command) in entries {
+        if system::interrupted() {
+            break;
+        }
+        println!("== {} ==", label);
+        let argv = if opt.no_shell {
+            command.split_whitespace().map(String::from).collect()
+        } else {
+            vec![command.to_string()]
+        };
+        let entry = Opt {
+            repetitions,
+            batch: None,
+            command: Some(Subcommands::Other(argv)),
+            label: Some(label.to_string()),
+            ..opt.clone()
         };
-        let mut frequencies: HashMap<u128, u128> = HashMap::new();
-        let mut max_freq = 0;
-        for tick in &mut ticks {
-            let rounded_time = *tick / rounding_quotient;
-            let mut i = *frequencies.get(&rounded_time).unwrap_or(&0);
-            i += 1;
-            frequencies.insert(rounded_time, i);
-            if i >= max_freq {
-                max_freq = i;
+        results.push((label, report(&entry, benchmark(&entry), &mut exported)));
+        println!();
+    }
+    print_ranking(opt, &mut results, baseline);
+}
+
+/// Builds and benchmarks the command at two git revisions and compares
+/// them. The revision checked out before is restored afterwards.
+fn run_git_bench(opt: &Opt) {
+    let (refs, build) = opt.git_bench.split_at(2);
+    if git::is_dirty().unwrap_or_else(|e| exit_with(Failure::Command, e)) {
+        exit_with(Failure::Usage, "--git-bench needs a clean work tree, commit or stash the changes first");
+    }
+    let original = git::current_ref().unwrap_or_else(|e| exit_with(Failure::Command, e));
+    // Whatever ends the benchmark, even a failing run, the original
+    // revision is checked out again.
+    exit::on_exit(move || {
+        if let Err(e) = git::checkout(&original) {
+            eprintln!("avgtime: warning: could not check out {} again: {}", original, e);
+        }
+    });
+    // A Ctrl-C during a checkout or build stops the loop below rather than
+    // avgtime, so the original revision is still restored.
+    system::catch_interrupts();
+    // Measure both revisions before reporting, so that a failed check in
+    // the report cannot leave the wrong revision checked out.
+    let mut measured = Vec::new();
+    for reference in refs {
+        if system::interrupted() {
+            break;
+        }
+        eprintln!("avgtime: building {}", reference);
+        let built = git::checkout(reference).and_then(|_| build_revision(&build[0]));
+        if system::interrupted() {
+            break;
+        }
+        if let Err(e) = built {
+            exit_with(Failure::Command, format!("{}: {}", reference, e));
+        }
+        measured.push((reference.as_str(), benchmark(opt)));
+    }
+    if measured.is_empty() {
+        exit_with(Failure::Command, "interrupted before any revision was measured");
+    }
+    exit::cleanup();
+
+    let mut results = Vec::new();
+    let mut exported = Vec::new();
+    for (reference, measurements) in measured {
+        println!("== {} ==", reference);
+        let entry = Opt { label: Some(reference.to_string()), ..opt.clone() };
+        results.push((reference, report(&entry, measurements, &mut exported)));
+        println!();
+    }
+    print_ranking(opt, &mut results, Some(refs[0].as_str()));
+}
+
+/// Runs the --git-bench build command, sending its output to stderr to
+/// keep the report on stdout clean.
+fn build_revision(build: &str) -> io::Result<()> {
+    let status = Command::new("sh").arg("-c").arg(build).stdout(io::stderr()).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("build command failed ({})", status)));
+    }
+    Ok(())
+}
+
+/// Prints benchmarks from fastest to slowest with their mean time relative
+/// to `baseline`, or to the fastest one.
+fn print_ranking(opt: &Opt, results: &mut [(&str, Totals)], baseline: Option<&str>) {
+    if results.is_empty() {
+        return;
+    }
+    results.sort_by(|a, b| a.1.mean().partial_cmp(&b.1.mean()).unwrap());
+    // An interrupted batch may stop before the baseline has run.
+    let baseline = baseline.filter(|baseline| {
+        let found = results.iter().any(|(label, _)| label == baseline);
+        if !found {
+            eprintln!("avgtime: warning: {} did not run, ranking relative to the fastest entry", baseline);
+        }
+        found
+    });
+    let (reference_label, reference) = match baseline {
+        Some(baseline) => *results.iter().find(|(label, _)| *label == baseline).unwrap(),
+        None => results[0],
+    };
+    let time = TimeFormat { precision: opt.precision, numbers: opt.number_format };
+    let width = results.iter().map(|(label, _)| label.len()).max().unwrap();
+    match baseline {
+        Some(baseline) => println!("Ranking (relative to {}):", baseline),
+        None => println!("Ranking:"),
+    }
+    for (rank, (label, totals)) in results.iter().enumerate() {
+        let mut line = format!("  {}. {:<width$}  {}  ({:.2}x)", rank + 1, label, time.ms(totals.mean()), totals.mean() / reference.mean().max(1.0), width = width);
+        if opt.compare_unpaired && *label != reference_label {
+            match welch_t_test(totals, &reference) {
+                Some(p) => line += &format!("  p={:.3}, {}", p, if p < 0.05 { "significant" } else { "not significant" }),
+                None => line += "  p=n/a, too few runs",
             }
         }
-        let mut histogram: HashMap<u128, u128> = HashMap::new();
-        for (bin,count) in &mut frequencies {
-            histogram.insert(*bin, *count);
+        println!("{}", line);
+    }
+    if opt.compare_unpaired {
+        println!("p-values: Welch's t-test against {} (assumes roughly normal run times, not equal variances); significant means p < 0.05", reference_label);
+    }
+}
+
+/// Runs the --tick-hook command after a run. `{time}` in the hook and
+/// `AVGTIME_TIME_MS` hold the duration in milliseconds; `AVGTIME_RUN` and
+/// `AVGTIME_STATUS` hold the run index and exit code.
+fn run_tick_hook(hook: &str, index: u32, elapsed: u128, status: &ExitStatus, precision: usize) {
+    let ms = format!("{:.*}", precision, elapsed as f64 / NANOS_PER_MS as f64);
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(hook.replace("{time}", &ms))
+        .env("AVGTIME_TIME_MS", &ms)
+        .env("AVGTIME_RUN", index.to_string())
+        .env("AVGTIME_STATUS", status.code().map(|code| code.to_string()).unwrap_or_default())
+        .status();
+    match result {
+        Ok(status) if !status.success() => eprintln!("avgtime: warning: --tick-hook failed for run #{} ({})", index, status),
+        Err(e) => eprintln!("avgtime: warning: could not run --tick-hook: {}", e),
+        Ok(_) => {}
+    }
+}
+
+/// Makes sure no other --exclusive benchmark runs at the same time, either
+/// by refusing to start or, with `wait`, by waiting for it to finish.
+fn take_exclusive_lock(wait: bool) -> Option<fs::File> {
+    let taken = system::open_lock_file().and_then(|file| {
+        if system::lock(&file, false)? {
+            return Ok(file);
+        }
+        if !wait {
+            exit_with(Failure::Busy, "another avgtime --exclusive benchmark is running, use --exclusive-wait to wait for it");
         }
+        eprintln!("avgtime: waiting for another --exclusive benchmark to finish");
+        system::lock(&file, true)?;
+        Ok(file)
+    });
+    taken.map_err(|e| eprintln!("avgtime: warning: could not take the --exclusive lock, running anyway: {}", e)).ok()
+}
 
-        let keys: Vec<&u128> = histogram.keys().collect::<Vec<&u128>>();
-        let mut sorted_keys = Vec::new();
-        for key in keys {
-            sorted_keys.push(key);
+/// Runs the --stats-command shell command with the stored run times on its
+/// stdin, one per line in dispatch order, in milliseconds with nanosecond
+/// precision. Its stdout goes straight into the report.
+fn run_stats_command(command: &str, runs: &[(u32, u128)]) {
+    let input: String = runs.iter()
+        .map(|(_, elapsed)| format!("{:.6}\n", *elapsed as f64 / NANOS_PER_MS as f64))
+        .collect();
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            let written = child.stdin.take().unwrap().write_all(input.as_bytes());
+            // A command that does not read all of its input is fine.
+            match written {
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+                _ => child.wait(),
+            }
+        });
+    match result {
+        Ok(status) if !status.success() => eprintln!("avgtime: warning: --stats-command failed ({})", status),
+        Err(e) => eprintln!("avgtime: warning: could not run --stats-command: {}", e),
+        Ok(_) => {}
+    }
+}
+
+/// Turns `\n` and `\t` typed on the command line into the real characters.
+fn unescape(template: &str) -> String {
+    template.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+/// P² estimators for every reported percentile under --approx-percentiles.
+fn percentile_estimators(opt: &Opt) -> Vec<(f64, P2Quantile)> {
+    if !opt.approx_percentiles {
+        return Vec::new();
+    }
+    PERCENTILE_TABLE.iter().map(|(_, p)| (*p, P2Quantile::new(*p))).collect()
+}
+
+/// Starts the worker pool, falling back to fewer threads with a warning
+/// when the system cannot create as many as requested.
+fn create_pool(requested: u32) -> ThreadPool {
+    // ThreadPool panics if a thread fails to spawn, so first find out how
+    // many threads can be started, holding them all until the count is in.
+    let gate = Arc::new(Mutex::new(()));
+    let held = gate.lock().unwrap();
+    let probes: Vec<_> = (0..requested)
+        .map_while(|_| {
+            let gate = gate.clone();
+            thread::Builder::new().spawn(move || drop(gate.lock())).ok()
+        })
+        .collect();
+    drop(held);
+    let size = probes.len();
+    for probe in probes {
+        probe.join().expect("Could not join probe thread");
+    }
+    if size == 0 {
+        exit_with(Failure::Command, "could not start a worker thread");
+    }
+    if size < requested as usize {
+        eprintln!("avgtime: warning: could only start {} of {} worker threads, running with -c {}", size, requested, size);
+    }
+    ThreadPool::new(size)
+}
+
+fn parse_args() -> Opt {
+    let args: Vec<OsString> = env::args_os().collect();
+    let mut opt = parse_from(&args);
+    if opt.list_presets {
+        for (name, description, preset) in PRESETS {
+            println!("{:<10}{} ({})", name, description, preset.join(" "));
         }
-        sorted_keys.sort();
-        println!("Histogram:");
-        println!("time:	count	normalized bar");
-        for rounded_time in sorted_keys {
-            let count = histogram[rounded_time];
-            let msecs = *rounded_time * rounding_quotient;
-            let bars = "#".repeat((count * 40 / max_freq) as usize);
-            println!("{}ms	{}	{}", msecs, count, bars)
+        process::exit(0);
+    }
+    if let Some(name) = &opt.preset {
+        let (_, _, preset) = PRESETS.iter().find(|(preset, _, _)| preset == name).unwrap_or_else(|| {
+            let names: Vec<&str> = PRESETS.iter().map(|(name, _, _)| *name).collect();
+            exit_with(Failure::Usage, format!("unknown preset {}, available presets are {}", name, names.join(", ")))
+        });
+        // Options given later override earlier ones, so the preset goes
+        // right after the program name where anything typed wins over it.
+        let with_preset: Vec<OsString> = args[..1].iter().cloned()
+            .chain(preset.iter().map(OsString::from))
+            .chain(args[1..].iter().cloned())
+            .collect();
+        opt = parse_from(&with_preset);
+    }
+    if opt.repetitions == 0 {
+        exit_with(Failure::Usage, "-r must be at least 1");
+    }
+    if opt.concurrency == 0 {
+        exit_with(Failure::Usage, "-c must be at least 1");
+    }
+    if opt.concurrency > MAX_CONCURRENCY {
+        eprintln!("avgtime: warning: -c {} is above the maximum of {}, using {}", opt.concurrency, MAX_CONCURRENCY, MAX_CONCURRENCY);
+        opt.concurrency = MAX_CONCURRENCY;
+    }
+    if opt.child_nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+        exit_with(Failure::Usage, "--child-nice must be between -20 and 19");
+    }
+    // Without a shell the warmup command is split into words, which must
+    // leave a program to run.
+    if opt.no_shell && opt.warmup_command.as_ref().is_some_and(|warmup| warmup.split_whitespace().next().is_none()) {
+        exit_with(Failure::Usage, "--warmup-command must not be empty");
+    }
+    if opt.sample == Some(0) {
+        exit_with(Failure::Usage, "--sample must be at least 1");
+    }
+    if let Some(template) = &opt.format_template {
+        // Check the placeholders before spending time on the runs.
+        let blank: Vec<(&str, String)> = TEMPLATE_FIELDS.iter().map(|name| (*name, String::new())).collect();
+        if let Err(e) = fill_template(template, &blank) {
+            exit_with(Failure::Usage, format!("--format-template: {}", e));
         }
     }
+    opt
 }
 
-fn run_command(cmd: &Vec<String>, quiet: bool) -> u128 {
-    let now = Instant::now();
-    let _output = if quiet {
-        Command::new("sh")
-            .arg("-c")
-            .args(cmd)
-            .stdout(Stdio::null()).stderr(Stdio::null())
-            .output().expect("failed to execute process")
-    } else {
-        Command::new("sh")
-            .arg("-c")
-            .args(cmd)
-            .stdout(Stdio::inherit()).stderr(Stdio::inherit())
-            .output().expect("failed to execute process")
+fn parse_from(args: &[OsString]) -> Opt {
+    let app = Opt::clap().setting(AppSettings::AllArgsOverrideSelf);
+    let matches = app.get_matches_from_safe(args).unwrap_or_else(|e| match e.kind {
+        structopt::clap::ErrorKind::HelpDisplayed | structopt::clap::ErrorKind::VersionDisplayed => e.exit(),
+        _ => exit_with(Failure::Usage, e.message.trim_start_matches("error: ")),
+    });
+    Opt::from_clap(&matches)
+}
+
+/// Warns when the reported precision hides the measurements, e.g. a command
+/// so fast that every run prints as `0.000ms`.
+fn warn_if_resolution_too_coarse(ticks: &[u128], time: &TimeFormat) {
+    let max = time.ms(*ticks.last().unwrap() as f64);
+    if max == time.ms(0.0) {
+        eprintln!("avgtime: warning: every run took less than the reported resolution, all times read as {}; increase --precision", max);
+    } else if ticks.len() > 1 && time.ms(*ticks.first().unwrap() as f64) == max {
+        eprintln!("avgtime: warning: all runs read as {} at this precision; increase --precision to see the distribution", max);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn create_cgroup(opt: &Opt) -> Option<cgroup::Cgroup> {
+    if opt.cgroup_cpus.is_none() && opt.cgroup_memory.is_none() {
+        return None;
+    }
+    cgroup::Cgroup::create(opt.cgroup_cpus.as_deref(), opt.cgroup_memory)
+        .map_err(|e| eprintln!("avgtime: warning: could not create a cgroup, running without limits: {}", e))
+        .ok()
+}
+
+#[cfg(target_os = "linux")]
+fn cgroup_procs(cgroup: &Option<cgroup::Cgroup>) -> Option<CString> {
+    cgroup.as_ref().map(|cgroup| cgroup.procs_path())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn create_cgroup(opt: &Opt) -> Option<()> {
+    if opt.cgroup_cpus.is_some() || opt.cgroup_memory.is_some() {
+        eprintln!("avgtime: warning: cgroups are only supported on Linux, running without limits");
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cgroup_procs(_cgroup: &Option<()>) -> Option<CString> {
+    None
+}
+
+#[cfg(unix)]
+fn killed_by(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn killed_by(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Running benchmarks through sudo changes PATH, permissions and more, so
+/// the measured command may not behave as it would for the user. There is
+/// nothing to warn about when an option that needs root was asked for, or
+/// when nothing is run at all.
+#[cfg(unix)]
+fn warn_if_root(opt: &Opt) {
+    let needs_root = opt.cgroup_cpus.is_some() || opt.cgroup_memory.is_some() || opt.child_nice.is_some_and(|nice| nice < 0);
+    if unsafe { libc::geteuid() } == 0 && !needs_root && opt.from_csv.is_none() {
+        eprintln!("avgtime: warning: running as root, the command may behave differently than for a normal user");
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_root(_opt: &Opt) {}
+
+fn parse_bin_width(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ms) if ms * NANOS_PER_MS as f64 >= 1.0 && ms.is_finite() => Ok(ms),
+        _ => Err(format!("bin width must be a positive number of milliseconds: {}", s)),
+    }
+}
+
+/// Parses a duration such as `250ms`, `1.5s`, `2m` or `1h`. A bare number is in seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| c.is_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.trim().parse().map_err(|_| format!("invalid duration: {}", s))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("invalid duration unit: {}", unit)),
     };
-    return now.elapsed().as_millis()
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!("invalid duration: {}", s));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses a wall-clock time such as `06:00` into hours and minutes.
+fn parse_clock_time(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid time, expected HH:MM: {}", s);
+    let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+    let hour: u32 = hour.parse().map_err(|_| invalid())?;
+    let minute: u32 = minute.parse().map_err(|_| invalid())?;
+    if hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    Ok((hour, minute))
+}
+
+/// Fills in the `{i}` and `{rand}` placeholders of the command for each run.
+struct Placeholders {
+    /// Generator and length for `{rand}`, when enabled.
+    random: Option<(Rng, usize)>,
+}
+
+impl Placeholders {
+    fn new(opt: &Opt) -> Placeholders {
+        Placeholders {
+            random: opt.random_arg.map(|length| {
+                (opt.seed.map(Rng::new).unwrap_or_else(Rng::from_time), length)
+            }),
+        }
+    }
+
+    fn expand(&mut self, cmds: &[Vec<String>], index: u32) -> Vec<Vec<String>> {
+        let index = index.to_string();
+        let random = self.random.as_mut().map(|(rng, length)| rng.alphanumeric(*length));
+        let expand = |arg: &String| {
+            let arg = arg.replace("{i}", &index);
+            match &random {
+                Some(random) => arg.replace("{rand}", random),
+                None => arg,
+            }
+        };
+        cmds.iter().map(|cmd| cmd.iter().map(expand).collect()).collect()
+    }
+}
+
+/// Times `empty_cmd`, a command that does nothing, to estimate the fixed
+/// cost of starting a process. Returns the median of several runs.
+fn measure_overhead(empty_cmd: &[String], options: &RunOptions) -> u128 {
+    // The --wait-for probe and the output handling belong to the measured
+    // command; the empty one only shares how runs are started and reaped.
+    let options = RunOptions { wait_for: None, capture: false, measure_output: false, ..options.clone() };
+    let mut samples: Vec<u128> = (0..CALIBRATION_RUNS)
+        .map(|_| {
+            run_command(&[empty_cmd.to_vec()], &options)
+                .unwrap_or_else(|e| exit_with(Failure::Command, format!("failed to execute calibration command: {}", e)))
+                .elapsed
+        })
+        .collect();
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+/// Runs the command until two consecutive runs differ by less than
+/// `threshold` percent, or `max_runs` is reached. Returns the number of
+/// runs performed and whether the timings stabilised.
+fn warm_up_until_stable(cmds: &[Vec<String>], placeholders: &mut Placeholders, options: &RunOptions, threshold: f64, max_runs: u32) -> (u32, bool) {
+    let mut previous: Option<u128> = None;
+    for run in 0..max_runs {
+        let elapsed = run_command(&placeholders.expand(cmds, run), options)
+            .unwrap_or_else(|e| exit_with(Failure::Command, format!("failed to execute process: {}", e)))
+            .elapsed;
+        if let Some(previous) = previous {
+            let change = (elapsed as f64 - previous as f64).abs() / previous.max(1) as f64 * 100.0;
+            if change < threshold {
+                return (run + 1, true);
+            }
+        }
+        previous = Some(elapsed);
+    }
+    (max_runs, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Opt {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        parse_from(&args)
+    }
+
+    #[test]
+    fn missing_command_is_rejected() {
+        assert_eq!(benchmarked_command(&parse(&["avgtime", "-r", "3"])), None);
+    }
+
+    #[test]
+    fn empty_command_is_rejected() {
+        assert_eq!(benchmarked_command(&parse(&["avgtime", ""])), None);
+        assert_eq!(benchmarked_command(&parse(&["avgtime", "-r", "3", "", "ignored"])), None);
+    }
+
+    #[test]
+    fn blank_group_member_is_rejected_without_a_shell() {
+        assert_eq!(benchmarked_command(&parse(&["avgtime", "--group", "--no-shell", "true", ""])), None);
+        assert_eq!(benchmarked_command(&parse(&["avgtime", "--group", "--no-shell", "true", " "])), None);
+        assert!(benchmarked_command(&parse(&["avgtime", "--group", "true", " "])).is_some());
+    }
+
+    #[test]
+    fn runs_finishing_out_of_order_are_ordered_the_same() {
+        let finished = [(3, 7), (0, 5), (4, 5), (1, 7), (2, 5), (5, 7)];
+        let mut reversed = finished;
+        reversed.reverse();
+        for mut runs in [finished, reversed] {
+            let ticks = order_runs(&mut runs);
+            assert_eq!(runs, [(0, 5), (1, 7), (2, 5), (3, 7), (4, 5), (5, 7)]);
+            assert_eq!(ticks, [5, 5, 5, 7, 7, 7]);
+        }
+    }
+
+    #[test]
+    fn command_with_arguments_is_kept() {
+        let opt = parse(&["avgtime", "-r", "3", "sleep", "0.1"]);
+        assert_eq!(benchmarked_command(&opt), Some(&vec!["sleep".to_string(), "0.1".to_string()]));
+    }
 }
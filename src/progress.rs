@@ -0,0 +1,59 @@
+//! Progress events for `--progress-fd`, written as JSON lines so that a
+//! supervising process can follow the benchmark while it runs.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::ExitStatus;
+use std::sync::Mutex;
+
+use crate::format::NANOS_PER_MS;
+
+pub struct Progress {
+    out: Mutex<File>,
+}
+
+impl Progress {
+    /// Writes to a duplicate of the already open file descriptor `fd`, so
+    /// that `fd` itself stays open for the next benchmark of a `--batch`
+    /// or `--git-bench` (Unix).
+    #[cfg(unix)]
+    pub fn open(fd: i32) -> io::Result<Progress> {
+        use std::os::unix::io::FromRawFd;
+        // Keep both descriptors out of the benchmarked commands, except for
+        // stdout or stderr, which the commands share.
+        if fd > libc::STDERR_FILENO && unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if copy == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Progress { out: Mutex::new(unsafe { File::from_raw_fd(copy) }) })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(_fd: i32) -> io::Result<Progress> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform"))
+    }
+
+    pub fn started(&self, run: u32) {
+        self.event(format!("{{\"event\": \"started\", \"run\": {}}}", run));
+    }
+
+    pub fn completed(&self, run: u32, elapsed: u128, status: &ExitStatus) {
+        let code = status.code().map_or("null".to_string(), |code| code.to_string());
+        self.event(format!("{{\"event\": \"completed\", \"run\": {}, \"ms\": {}, \"status\": {}}}",
+            run, elapsed as f64 / NANOS_PER_MS as f64, code));
+    }
+
+    pub fn done(&self, runs: usize) {
+        self.event(format!("{{\"event\": \"done\", \"runs\": {}}}", runs));
+    }
+
+    /// Writes one event line. A reader that went away must not stop the
+    /// benchmark, so write errors are ignored.
+    fn event(&self, line: String) {
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{}", line);
+    }
+}
@@ -0,0 +1,42 @@
+//! A small seedable pseudo-random generator (SplitMix64) for generating
+//! command inputs. Not suitable for anything security related.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Seeds from the current time.
+    pub fn from_time() -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        Rng::new(nanos as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Returns a random string of `length` ASCII letters and digits.
+    pub fn alphanumeric(&mut self, length: usize) -> String {
+        (0..length)
+            .map(|_| ALPHANUMERIC[(self.next_u64() % ALPHANUMERIC.len() as u64) as usize] as char)
+            .collect()
+    }
+}
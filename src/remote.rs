@@ -0,0 +1,69 @@
+//! Running the benchmarked command on another host over ssh.
+//!
+//! A control master connection is opened once up front, so the ssh
+//! handshake is not part of every measured run.
+
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+
+/// Seconds the control master stays up once no run is using it. `Drop`
+/// closes it straight away; this only matters when avgtime exits without
+/// unwinding, so that no master is left behind.
+const CONTROL_PERSIST_SECS: u32 = 60;
+
+pub struct Remote {
+    host: String,
+    control_path: PathBuf,
+}
+
+impl Remote {
+    /// Opens a persistent control master connection to `host`.
+    pub fn connect(host: &str) -> io::Result<Remote> {
+        let remote = Remote {
+            host: host.to_string(),
+            control_path: env::temp_dir().join(format!("avgtime-ssh-{}", process::id())),
+        };
+        let status = Command::new("ssh")
+            .args(["-M", "-N", "-f", "-o"])
+            .arg(format!("ControlPersist={}", CONTROL_PERSIST_SECS))
+            .arg("-o")
+            .arg(remote.control_option())
+            .arg(&remote.host)
+            .stdin(Stdio::null())
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("could not connect to {}", host)));
+        }
+        Ok(remote)
+    }
+
+    /// Arguments that run a command on the remote host through the master.
+    pub fn command_prefix(&self) -> Vec<String> {
+        vec![
+            "ssh".to_string(),
+            "-o".to_string(),
+            self.control_option(),
+            "-o".to_string(),
+            "ControlMaster=no".to_string(),
+            self.host.clone(),
+            "--".to_string(),
+        ]
+    }
+
+    fn control_option(&self) -> String {
+        format!("ControlPath={}", self.control_path.display())
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        let _ = Command::new("ssh")
+            .args(["-O", "exit", "-o"])
+            .arg(self.control_option())
+            .arg(&self.host)
+            .stderr(Stdio::null())
+            .status();
+    }
+}
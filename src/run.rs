@@ -0,0 +1,404 @@
+//! Running a single repetition of the benchmarked command.
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+#[cfg(unix)]
+use std::fs;
+use std::io::{self, PipeReader, Read};
+use std::ops::Add;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+#[cfg(unix)]
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(target_os = "linux")]
+use crate::cgroup;
+
+/// Size of the reads used when capturing output.
+const CAPTURE_CHUNK: usize = 8192;
+/// How often the `--wait-for` probe is retried.
+const PROBE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Process ids of the commands that have been started and not yet reaped,
+/// for `terminate_running` (Unix).
+#[cfg(unix)]
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Settings shared by every run of the command.
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    /// Discard the command's stdout.
+    pub quiet_stdout: bool,
+    /// Discard the command's stderr.
+    pub quiet_stderr: bool,
+    /// Keep the command's stdout and stderr in the result instead of
+    /// passing them through. Takes precedence over the quiet settings.
+    pub capture: bool,
+    /// When capturing, send stderr into the same pipe as stdout so the
+    /// captured output keeps the order it was written in. When measuring,
+    /// count stderr along with stdout.
+    pub merge_output: bool,
+    /// Most bytes of each captured stream kept; earlier output is dropped.
+    pub max_capture: usize,
+    /// Collect context switch and page fault counts for each run (Unix).
+    pub resource_stats: bool,
+    /// `cgroup.procs` of the group each run is moved into (Linux).
+    pub cgroup: Option<CString>,
+    /// Scheduling priority the command starts with (Unix).
+    pub nice: Option<i32>,
+    /// Shell command polled after the main command exits; the run only
+    /// ends once it succeeds.
+    pub wait_for: Option<String>,
+    /// How long `wait_for` is polled before the run fails.
+    pub wait_for_timeout: Duration,
+    /// Count the bytes the command writes to stdout instead of passing
+    /// them through.
+    pub measure_output: bool,
+}
+
+#[derive(Debug)]
+pub struct RunResult {
+    /// Wall time of the run in nanoseconds.
+    pub elapsed: u128,
+    /// When the run started, by the system clock.
+    pub started: SystemTime,
+    pub status: ExitStatus,
+    /// Captured output, empty unless `RunOptions::capture` is set. With
+    /// `merge_output` everything is in `stdout`.
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Set when `RunOptions::resource_stats` is enabled and supported.
+    pub usage: Option<ResourceUsage>,
+    /// Bytes written to stdout, when `RunOptions::measure_output` is set.
+    pub stdout_bytes: Option<u64>,
+}
+
+/// Counters from `getrusage` for a single run.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceUsage {
+    pub voluntary_switches: i64,
+    pub involuntary_switches: i64,
+    pub major_faults: i64,
+    pub minor_faults: i64,
+}
+
+impl Add for ResourceUsage {
+    type Output = ResourceUsage;
+
+    fn add(self, other: ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            voluntary_switches: self.voluntary_switches + other.voluntary_switches,
+            involuntary_switches: self.involuntary_switches + other.involuntary_switches,
+            major_faults: self.major_faults + other.major_faults,
+            minor_faults: self.minor_faults + other.minor_faults,
+        }
+    }
+}
+
+/// Runs the commands of one repetition, each a program followed by its
+/// arguments, in parallel and measures the wall time until all have exited.
+/// The status is that of the first command that failed, if any.
+pub fn run_command(cmds: &[Vec<String>], options: &RunOptions) -> io::Result<RunResult> {
+    let started = SystemTime::now();
+    let now = Instant::now();
+    let running = cmds.iter()
+        .map(|cmd| spawn(cmd, options))
+        .collect::<io::Result<Vec<_>>>()?;
+    let finished = if running.len() == 1 {
+        running.into_iter().map(|(child, merged)| finish(child, merged, options)).collect::<io::Result<Vec<_>>>()?
+    } else {
+        // Drain every member in its own thread, so that one filling its
+        // pipe is not held up until the members before it have exited.
+        thread::scope(|scope| {
+            let members: Vec<_> = running.into_iter()
+                .map(|(child, merged)| scope.spawn(move || finish(child, merged, options)))
+                .collect();
+            members.into_iter().map(|member| member.join().expect("output reader panicked")).collect::<io::Result<Vec<_>>>()
+        })?
+    };
+    let mut result: Option<RunResult> = None;
+    for finished in finished {
+        result = Some(match result {
+            None => finished,
+            Some(mut result) => {
+                if result.status.success() {
+                    result.status = finished.status;
+                }
+                result.stdout.extend(finished.stdout);
+                result.stderr.extend(finished.stderr);
+                result.usage = match (result.usage, finished.usage) {
+                    (Some(a), Some(b)) => Some(a + b),
+                    (a, b) => a.or(b),
+                };
+                result.stdout_bytes = match (result.stdout_bytes, finished.stdout_bytes) {
+                    (Some(a), Some(b)) => Some(a + b),
+                    (a, b) => a.or(b),
+                };
+                result
+            }
+        });
+    }
+    let mut result = result.expect("no command to run");
+    if let Some(probe) = &options.wait_for {
+        wait_for(probe, options.wait_for_timeout)?;
+    }
+    result.elapsed = now.elapsed().as_nanos();
+    result.started = started;
+    Ok(result)
+}
+
+/// Starts `cmd`, returning the child and, when merging captured or
+/// measured output, the read end of the pipe shared by its stdout and
+/// stderr.
+fn spawn(cmd: &[String], options: &RunOptions) -> io::Result<(Child, Option<PipeReader>)> {
+    let program = cmd.first().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no command provided"))?;
+    let mut command = Command::new(program);
+    command.args(&cmd[1..]);
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(procs) = options.cgroup.clone() {
+            use std::os::unix::process::CommandExt;
+            unsafe {
+                command.pre_exec(move || cgroup::join(&procs));
+            }
+        }
+    }
+    #[cfg(unix)]
+    {
+        if let Some(nice) = options.nice {
+            use std::os::unix::process::CommandExt;
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+    let mut merged = None;
+    if (options.capture || options.measure_output) && options.merge_output {
+        let (reader, writer) = io::pipe()?;
+        command.stdout(writer.try_clone()?).stderr(writer);
+        merged = Some(reader);
+    } else if options.capture {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    } else {
+        let stream = |quiet| if quiet { Stdio::null() } else { Stdio::inherit() };
+        let stdout = if options.measure_output { Stdio::piped() } else { stream(options.quiet_stdout) };
+        command.stdout(stdout).stderr(stream(options.quiet_stderr));
+    }
+    let child = command.spawn().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(e.kind(), format!("command not found: {}", program)),
+        _ => e,
+    })?;
+    #[cfg(unix)]
+    RUNNING.lock().unwrap().push(child.id());
+    // The command still holds our copy of a merged pipe's write end; close
+    // it so reading stops once the child exits.
+    drop(command);
+    Ok((child, merged))
+}
+
+/// Sends SIGTERM to every command that is still running and, where `/proc`
+/// lists them, to the processes it started, such as the program a shell
+/// is running (Unix).
+#[cfg(unix)]
+pub fn terminate_running() {
+    let running = RUNNING.lock().unwrap();
+    // Find the whole tree before signalling any of it, since the children
+    // of a shell that exits are handed to init.
+    let mut pids = running.clone();
+    let mut next = 0;
+    while next < pids.len() {
+        pids.extend(children(pids[next]));
+        next += 1;
+    }
+    for pid in pids {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+}
+
+/// The processes started by `pid` that are still running (Linux).
+#[cfg(unix)]
+fn children(pid: u32) -> Vec<u32> {
+    let tasks = match fs::read_dir(format!("/proc/{}/task", pid)) {
+        Ok(tasks) => tasks,
+        Err(_) => return Vec::new(),
+    };
+    tasks.flatten()
+        .filter_map(|task| fs::read_to_string(task.path().join("children")).ok())
+        .flat_map(|children| children.split_whitespace().filter_map(|child| child.parse().ok()).collect::<Vec<_>>())
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn terminate_running() {}
+
+/// Collects the output of a spawned child and waits for it to exit.
+fn finish(mut child: Child, merged: Option<PipeReader>, options: &RunOptions) -> io::Result<RunResult> {
+    // Output that is only counted is not kept.
+    let limit = if options.capture { options.max_capture } else { 0 };
+    let ((stdout, stdout_bytes), stderr) = match merged {
+        Some(reader) => (read_tail(reader, limit)?, Vec::new()),
+        None => read_output(&mut child, limit)?,
+    };
+    let (status, usage) = wait(child, options.resource_stats)?;
+    Ok(RunResult {
+        elapsed: 0,
+        started: SystemTime::now(),
+        status,
+        stdout,
+        stderr,
+        usage,
+        stdout_bytes: Some(stdout_bytes).filter(|_| options.measure_output),
+    })
+}
+
+/// The kept end of a stream and the total number of bytes read from it.
+type Tail = (Vec<u8>, u64);
+
+/// Reads the piped stdout and stderr of `child`, if any, without letting
+/// either pipe fill up while the other is read. Returns the kept stdout
+/// with its total length, and the kept stderr.
+fn read_output(child: &mut Child, limit: usize) -> io::Result<(Tail, Vec<u8>)> {
+    let stderr = child.stderr.take().map(|pipe| thread::spawn(move || read_tail(pipe, limit)));
+    let stdout = match child.stdout.take() {
+        Some(pipe) => read_tail(pipe, limit)?,
+        None => (Vec::new(), 0),
+    };
+    let stderr = match stderr {
+        Some(reader) => reader.join().expect("stderr reader panicked")?.0,
+        None => Vec::new(),
+    };
+    Ok((stdout, stderr))
+}
+
+/// Reads `reader` to the end, keeping only the last `limit` bytes so that
+/// chatty commands cannot exhaust memory.
+fn read_tail(mut reader: impl Read, limit: usize) -> io::Result<Tail> {
+    let mut tail = VecDeque::new();
+    let mut total = 0;
+    let mut chunk = [0; CAPTURE_CHUNK];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        total += read as u64;
+        tail.extend(&chunk[..read]);
+        let excess = tail.len().saturating_sub(limit);
+        tail.drain(..excess);
+    }
+    Ok((tail.into(), total))
+}
+
+/// Waits for `child`, collecting its resource usage through `wait4` when
+/// `resource_stats` is set.
+#[cfg(unix)]
+fn wait(mut child: Child, resource_stats: bool) -> io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    // Wait for the exit without reaping the child, and only reap it once it
+    // is off the running list, so `terminate_running` cannot signal a
+    // process that has reused its pid.
+    let pid = child.id();
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    while unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT) } == -1 {
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+    let mut running = RUNNING.lock().unwrap();
+    running.retain(|running| *running != pid);
+    if !resource_stats {
+        return Ok((child.wait()?, None));
+    }
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    while unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) } == -1 {
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+    Ok((ExitStatus::from_raw(status), Some(ResourceUsage {
+        voluntary_switches: usage.ru_nvcsw as i64,
+        involuntary_switches: usage.ru_nivcsw as i64,
+        major_faults: usage.ru_majflt as i64,
+        minor_faults: usage.ru_minflt as i64,
+    })))
+}
+
+#[cfg(not(unix))]
+fn wait(mut child: Child, _resource_stats: bool) -> io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    Ok((child.wait()?, None))
+}
+
+/// Polls the `probe` shell command until it exits successfully, or fails
+/// once `timeout` has passed.
+fn wait_for(probe: &str, timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(probe)
+            .stdout(Stdio::null()).stderr(Stdio::null())
+            .status()?;
+        if status.success() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!(
+                "--wait-for probe did not succeed within {}s: {}", timeout.as_secs_f64(), probe,
+            )));
+        }
+        thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_program_is_reported_by_name() {
+        let cmds = vec![vec!["avgtime-no-such-command".to_string(), "--flag".to_string()]];
+        let error = run_command(&cmds, &RunOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(error.to_string(), "command not found: avgtime-no-such-command");
+    }
+
+    #[test]
+    fn missing_group_member_fails_the_repetition() {
+        let cmds = vec![vec!["true".to_string()], vec!["avgtime-no-such-command".to_string()]];
+        assert_eq!(run_command(&cmds, &RunOptions::default()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn wait_for_gives_up_on_a_failing_probe() {
+        let options = RunOptions {
+            wait_for: Some("false".to_string()),
+            wait_for_timeout: Duration::from_millis(50),
+            ..RunOptions::default()
+        };
+        let error = run_command(&[vec!["true".to_string()]], &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(error.to_string(), "--wait-for probe did not succeed within 0.05s: false");
+    }
+
+    #[test]
+    fn empty_group_member_is_invalid_input() {
+        let cmds = vec![vec!["true".to_string()], Vec::new()];
+        assert_eq!(run_command(&cmds, &RunOptions::default()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
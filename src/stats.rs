@@ -0,0 +1,455 @@
+//! Statistics over the sorted run durations.
+
+use std::str::FromStr;
+
+/// How a percentile is picked from the sorted samples.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PercentileMethod {
+    /// Smallest sample with at least `p` of the data at or below it.
+    /// NumPy `inverted_cdf`, Hyndman & Fan type 1.
+    Nearest,
+    /// Linear interpolation between the closest ranks.
+    /// NumPy `linear` (its default) and Excel `PERCENTILE.INC`, type 7.
+    Linear,
+    /// Nearest rank, averaging the two neighbouring samples when the rank
+    /// falls exactly on a sample. NumPy `averaged_inverted_cdf`, type 2.
+    Midpoint,
+}
+
+impl PercentileMethod {
+    pub const NAMES: &'static [&'static str] = &["nearest", "linear", "midpoint"];
+}
+
+impl FromStr for PercentileMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(PercentileMethod::Nearest),
+            "linear" => Ok(PercentileMethod::Linear),
+            "midpoint" => Ok(PercentileMethod::Midpoint),
+            _ => Err(format!("unknown percentile method: {}", s)),
+        }
+    }
+}
+
+/// Returns the `p` percentile (0.0 to 1.0) of the non-empty, sorted `ticks`.
+pub fn percentile(ticks: &[u128], p: f64, method: PercentileMethod) -> f64 {
+    let n = ticks.len();
+    let last = n - 1;
+    match method {
+        PercentileMethod::Nearest => {
+            let rank = (p * n as f64).ceil() as usize;
+            ticks[rank.clamp(1, n) - 1] as f64
+        }
+        PercentileMethod::Linear => {
+            let h = p * last as f64;
+            let lower = (h.floor() as usize).min(last);
+            let upper = (lower + 1).min(last);
+            let fraction = h - lower as f64;
+            ticks[lower] as f64 + (ticks[upper] as f64 - ticks[lower] as f64) * fraction
+        }
+        PercentileMethod::Midpoint => {
+            let index = p * n as f64 - 1.0;
+            if index == index.round() {
+                let i = (index.max(0.0) as usize).min(last);
+                (ticks[i] as f64 + ticks[(i + 1).min(last)] as f64) / 2.0
+            } else {
+                ticks[(index.ceil().max(0.0) as usize).min(last)] as f64
+            }
+        }
+    }
+}
+
+/// Returns the arithmetic mean of `ticks`, or 0 when empty.
+pub fn mean(ticks: &[u128]) -> f64 {
+    if ticks.is_empty() {
+        return 0.0;
+    }
+    ticks.iter().map(|t| *t as f64).sum::<f64>() / ticks.len() as f64
+}
+
+/// Running totals over every run, kept even when only a sample of the
+/// individual durations is stored. The mean and variance are updated with
+/// Welford's algorithm, which stays accurate where a sum of squares would
+/// lose precision.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Totals {
+    pub count: usize,
+    pub sum: u128,
+    mean: f64,
+    /// Sum of squared differences from the current mean.
+    m2: f64,
+    /// Shortest and longest duration, each with the earliest run index
+    /// that took it.
+    pub min: Option<(u128, u32)>,
+    pub max: Option<(u128, u32)>,
+}
+
+impl Totals {
+    pub fn add(&mut self, index: u32, value: u128) {
+        self.count += 1;
+        self.sum += value;
+        let delta = value as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value as f64 - self.mean);
+        if self.min.is_none_or(|(min, run)| value < min || (value == min && index < run)) {
+            self.min = Some((value, index));
+        }
+        if self.max.is_none_or(|(max, run)| value > max || (value == max && index < run)) {
+            self.max = Some((value, index));
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Unbiased estimate of the variance of the population sampled.
+    pub fn sample_variance(&self) -> f64 {
+        self.m2 / (self.count.max(2) - 1) as f64
+    }
+
+    /// Population standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        (self.m2 / self.count.max(1) as f64).sqrt()
+    }
+}
+
+/// Streaming estimate of a single quantile using the P² algorithm (Jain
+/// and Chlamtac, 1985): five markers are kept and adjusted as values
+/// arrive, so memory stays constant however many runs are made.
+#[derive(Clone, Debug)]
+pub struct P2Quantile {
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    pub fn new(p: f64) -> P2Quantile {
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    pub fn add(&mut self, value: u128) {
+        let x = value as f64;
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell the value falls into, stretching the extremes.
+        let cell = if x < self.heights[0] {
+            self.heights[0] = x;
+            0
+        } else if x >= self.heights[4] {
+            self.heights[4] = x;
+            3
+        } else {
+            (1..5).find(|i| x < self.heights[*i]).unwrap() - 1
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(&self.increments) {
+            *desired += increment;
+        }
+
+        for i in 1..4 {
+            let offset = self.desired[i] - self.positions[i];
+            if (offset >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (offset <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0) {
+                let d = offset.signum();
+                let parabolic = self.parabolic(i, d);
+                self.heights[i] = if self.heights[i - 1] < parabolic && parabolic < self.heights[i + 1] {
+                    parabolic
+                } else {
+                    self.linear(i, d)
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    /// Returns the current estimate, or 0 before any value was added.
+    /// Until five values have been seen it is the nearest-rank quantile.
+    pub fn estimate(&self) -> f64 {
+        if self.count >= 5 {
+            return self.heights[2];
+        }
+        let mut seen = self.heights[..self.count].to_vec();
+        seen.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        match seen.len() {
+            0 => 0.0,
+            n => seen[(self.p * (n - 1) as f64).round() as usize],
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        self.heights[i] + d * (self.heights[j] - self.heights[i]) / (self.positions[j] - self.positions[i])
+    }
+}
+
+/// Two-sided p-value of Welch's t-test for a difference between the means
+/// of two independent samples. Assumes roughly normal samples but not
+/// equal variances. `None` when either sample has fewer than two values.
+pub fn welch_t_test(a: &Totals, b: &Totals) -> Option<f64> {
+    if a.count < 2 || b.count < 2 {
+        return None;
+    }
+    let va = a.sample_variance() / a.count as f64;
+    let vb = b.sample_variance() / b.count as f64;
+    if va + vb == 0.0 {
+        return Some(if a.mean() == b.mean() { 1.0 } else { 0.0 });
+    }
+    let t = (a.mean() - b.mean()) / (va + vb).sqrt();
+    let df = (va + vb).powi(2) / (va * va / (a.count - 1) as f64 + vb * vb / (b.count - 1) as f64);
+    Some(incomplete_beta(df / (df + t * t), df / 2.0, 0.5))
+}
+
+/// Regularized incomplete beta function I_x(a, b), evaluated with the
+/// continued fraction from Numerical Recipes.
+fn incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    // The continued fraction converges quickly only below this point.
+    if x > (a + 1.0) / (a + b + 2.0) {
+        return 1.0 - incomplete_beta(1.0 - x, b, a);
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    d = 1.0 / if d.abs() < TINY { TINY } else { d };
+    let mut fraction = d;
+    for m in 1..200 {
+        let m = m as f64;
+        for numerator in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            d = 1.0 / if d.abs() < TINY { TINY } else { d };
+            c = 1.0 + numerator / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            fraction *= c * d;
+        }
+        if (c * d - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    front * fraction / a
+}
+
+/// Natural logarithm of the gamma function (Lanczos approximation).
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46, -86.505_320_329_416_77, 24.014_098_240_830_91,
+        -1.231_739_572_450_155, 0.001_208_650_973_866_179, -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS.iter().enumerate()
+        .fold(1.000_000_000_190_015, |sum, (i, c)| sum + c / (x + 1.0 + i as f64));
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    // The example data set used by Wikipedia's percentile article, with the
+    // values NumPy gives for the matching `method`.
+    const DATA: [u128; 5] = [15, 20, 35, 40, 50];
+
+    #[test]
+    fn nearest_matches_numpy_inverted_cdf() {
+        let cases = [(0.05, 15.0), (0.3, 20.0), (0.4, 20.0), (0.5, 35.0), (0.95, 50.0), (1.0, 50.0)];
+        for (p, expected) in cases {
+            assert_eq!(percentile(&DATA, p, PercentileMethod::Nearest), expected, "p = {}", p);
+        }
+    }
+
+    #[test]
+    fn linear_matches_numpy_linear() {
+        let cases = [(0.0, 15.0), (0.3, 23.0), (0.4, 29.0), (0.5, 35.0), (0.95, 48.0), (1.0, 50.0)];
+        for (p, expected) in cases {
+            let actual = percentile(&DATA, p, PercentileMethod::Linear);
+            assert!((actual - expected).abs() < 1e-9, "p = {}: {} != {}", p, actual, expected);
+        }
+    }
+
+    #[test]
+    fn midpoint_matches_numpy_averaged_inverted_cdf() {
+        let cases = [(0.05, 15.0), (0.3, 20.0), (0.5, 35.0), (0.95, 50.0)];
+        for (p, expected) in cases {
+            assert_eq!(percentile(&DATA, p, PercentileMethod::Midpoint), expected, "p = {}", p);
+        }
+    }
+
+    #[test]
+    fn midpoint_averages_on_exact_rank() {
+        // p * n lands exactly on a sample, so its neighbour is averaged in.
+        assert_eq!(percentile(&DATA, 0.2, PercentileMethod::Midpoint), 17.5);
+        assert_eq!(percentile(&DATA, 0.4, PercentileMethod::Midpoint), 27.5);
+        assert_eq!(percentile(&DATA, 1.0, PercentileMethod::Midpoint), 50.0);
+    }
+
+    #[test]
+    fn single_sample() {
+        for method in [PercentileMethod::Nearest, PercentileMethod::Linear, PercentileMethod::Midpoint] {
+            assert_eq!(percentile(&[7], 0.95, method), 7.0);
+        }
+    }
+
+    /// Population variance from the exact mean, the textbook two-pass way.
+    fn two_pass_variance(values: &[u128]) -> f64 {
+        let mean = values.iter().sum::<u128>() as f64 / values.len() as f64;
+        values.iter().map(|v| (*v as f64 - mean).powi(2)).sum::<f64>() / values.len() as f64
+    }
+
+    fn totals_of(values: &[u128]) -> Totals {
+        let mut totals = Totals::default();
+        for (i, value) in values.iter().enumerate() {
+            totals.add(i as u32, *value);
+        }
+        totals
+    }
+
+    #[test]
+    fn welford_matches_two_pass() {
+        let values = [12_345_678, 9_876_543, 10_000_000, 11_111_111, 15_000_000, 8_765_432];
+        let totals = totals_of(&values);
+        let expected_mean = values.iter().sum::<u128>() as f64 / values.len() as f64;
+        assert!((totals.mean() - expected_mean).abs() < 1e-6);
+        let expected = two_pass_variance(&values).sqrt();
+        assert!((totals.std_dev() - expected).abs() < 1e-6 * expected, "{} != {}", totals.std_dev(), expected);
+    }
+
+    #[test]
+    fn welford_keeps_precision_for_large_values() {
+        // Runs of about 11.5 days in nanoseconds that differ by a few
+        // nanoseconds: the squares are around 1e30, far beyond what f64
+        // can hold exactly, so sum-of-squares variance comes out as noise.
+        let base = 1_000_000_000_000_000;
+        let values = [base + 4, base + 7, base + 13, base + 16];
+        let totals = totals_of(&values);
+        assert_eq!(two_pass_variance(&values), 22.5);
+        assert!((totals.std_dev() - 22.5f64.sqrt()).abs() < 1e-9, "{}", totals.std_dev());
+        assert!((totals.sample_variance() - 30.0).abs() < 1e-9, "{}", totals.sample_variance());
+    }
+
+    #[test]
+    fn ties_go_to_the_earliest_run() {
+        // Many duplicate durations, added in completion order rather than
+        // by index, as concurrent runs arrive.
+        let mut totals = Totals::default();
+        for (index, value) in [(4, 3), (2, 9), (1, 3), (7, 9), (0, 5), (3, 3), (5, 9), (6, 5)] {
+            totals.add(index, value);
+        }
+        assert_eq!(totals.min, Some((3, 1)));
+        assert_eq!(totals.max, Some((9, 2)));
+    }
+
+    fn p2_of(p: f64, values: impl IntoIterator<Item = u128>) -> f64 {
+        let mut estimator = P2Quantile::new(p);
+        for value in values {
+            estimator.add(value);
+        }
+        estimator.estimate()
+    }
+
+    #[test]
+    fn p2_uses_nearest_rank_before_five_values() {
+        assert_eq!(p2_of(0.5, []), 0.0);
+        assert_eq!(p2_of(0.5, [30, 10, 20]), 20.0);
+        assert_eq!(p2_of(0.95, [30, 10]), 30.0);
+        assert_eq!(p2_of(0.05, [40, 10, 30, 20]), 10.0);
+    }
+
+    #[test]
+    fn p2_follows_constant_and_monotone_streams() {
+        assert_eq!(p2_of(0.95, std::iter::repeat_n(7, 1000)), 7.0);
+        let rising = p2_of(0.5, 1..=1000);
+        assert!((rising - 500.5).abs() < 1.0, "{}", rising);
+        let falling = p2_of(0.9, (1..=1000).rev());
+        assert!((falling - 900.5).abs() < 1.0, "{}", falling);
+    }
+
+    #[test]
+    fn p2_agrees_with_the_exact_percentile() {
+        let mut rng = Rng::new(42);
+        let mut values: Vec<u128> = (0..5000).map(|_| u128::from(rng.below(1_000_000))).collect();
+        let estimates: Vec<(f64, f64)> = [0.5, 0.95, 0.99].iter().map(|p| (*p, p2_of(*p, values.iter().copied()))).collect();
+        values.sort_unstable();
+        for (p, estimate) in estimates {
+            let exact = percentile(&values, p, PercentileMethod::Linear);
+            // Within half a percent of the range of the values.
+            assert!((estimate - exact).abs() < 5_000.0, "p = {}: {} != {}", p, estimate, exact);
+        }
+    }
+
+    #[test]
+    fn welch_matches_reference_p_values() {
+        // Two-sided p-values computed with mpmath's regularized incomplete
+        // beta function from the same t and Welch-Satterthwaite df.
+        let cases: [(&[u128], &[u128], f64); 2] = [
+            (&[10, 12, 11, 13, 12, 11, 10, 12], &[13, 14, 12, 15, 14, 13, 15, 10], 0.020_146_945_48),
+            (&[19, 22, 20, 24, 21, 23, 20, 22], &[24, 22, 27, 23, 26, 25, 21, 28], 0.011_265_752_70),
+        ];
+        for (a, b, expected) in cases {
+            let p = welch_t_test(&totals_of(a), &totals_of(b)).unwrap();
+            assert!((p - expected).abs() < 1e-8, "{} != {}", p, expected);
+            assert_eq!(welch_t_test(&totals_of(b), &totals_of(a)), Some(p));
+        }
+    }
+
+    #[test]
+    fn welch_finds_no_difference_between_identical_samples() {
+        let a = totals_of(&[10, 12, 11, 13, 12]);
+        assert_eq!(welch_t_test(&a, &a.clone()), Some(1.0));
+    }
+
+    #[test]
+    fn welch_without_variance_compares_the_means() {
+        let five = totals_of(&[5, 5, 5]);
+        assert_eq!(welch_t_test(&five, &totals_of(&[5, 5])), Some(1.0));
+        assert_eq!(welch_t_test(&five, &totals_of(&[7, 7, 7])), Some(0.0));
+    }
+
+    #[test]
+    fn welch_needs_two_runs_on_each_side() {
+        let a = totals_of(&[10, 12, 11]);
+        assert_eq!(welch_t_test(&a, &totals_of(&[11])), None);
+        assert_eq!(welch_t_test(&totals_of(&[]), &a), None);
+    }
+}
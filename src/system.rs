@@ -0,0 +1,155 @@
+//! Information about the machine avgtime runs on.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Name of the file in the temporary directory that `--exclusive` locks.
+const LOCK_FILE: &str = "avgtime.lock";
+/// Readings taken by `clock_granularity`.
+const GRANULARITY_SAMPLES: u32 = 1000;
+
+/// Returns the 1-minute load average, where available (Linux).
+pub fn load_average() -> Option<f64> {
+    let loadavg = fs::read_to_string("/proc/loadavg").ok()?;
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Estimates the resolution of the monotonic clock as the smallest non-zero
+/// difference between consecutive `Instant::now()` readings.
+pub fn clock_granularity() -> Duration {
+    let mut smallest = Duration::MAX;
+    for _ in 0..GRANULARITY_SAMPLES {
+        let start = Instant::now();
+        let mut now = Instant::now();
+        while now == start {
+            now = Instant::now();
+        }
+        smallest = smallest.min(now - start);
+    }
+    smallest
+}
+
+/// Returns the time left until the next `hour:minute` on the local clock:
+/// today, or tomorrow if that has already passed (Unix).
+#[cfg(unix)]
+pub fn until_local_time(hour: u32, minute: u32) -> Option<Duration> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    let seconds = now.as_secs() as libc::time_t;
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&seconds, &mut local) }.is_null() {
+        return None;
+    }
+    let since_midnight = Duration::new((local.tm_hour * 3600 + local.tm_min * 60 + local.tm_sec) as u64, now.subsec_nanos());
+    let target = Duration::from_secs(u64::from(hour * 3600 + minute * 60));
+    if target > since_midnight {
+        Some(target - since_midnight)
+    } else {
+        Some(target + Duration::from_secs(24 * 3600) - since_midnight)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn until_local_time(_hour: u32, _minute: u32) -> Option<Duration> {
+    None
+}
+
+/// Host name of the machine, where available.
+pub fn hostname() -> Option<String> {
+    let name = fs::read_to_string("/proc/sys/kernel/hostname").ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())?;
+    Some(name.trim().to_string())
+}
+
+/// CPU model name from `/proc/cpuinfo` (Linux).
+pub fn cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo.lines()
+        .find(|line| line.starts_with("model name"))
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, model)| model.trim().to_string())
+}
+
+/// Operating system name, kernel release and architecture as reported by
+/// `uname` (Unix).
+#[cfg(unix)]
+pub fn os_description() -> String {
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return std::env::consts::OS.to_string();
+    }
+    let field = |chars: &[libc::c_char]| {
+        unsafe { std::ffi::CStr::from_ptr(chars.as_ptr()) }.to_string_lossy().into_owned()
+    };
+    format!("{} {} {}", field(&name.sysname), field(&name.release), field(&name.machine))
+}
+
+#[cfg(not(unix))]
+pub fn os_description() -> String {
+    format!("{} {}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Set once SIGINT or SIGTERM has been received.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes SIGINT and SIGTERM ask for a graceful stop instead of killing
+/// avgtime, so the runs made so far can still be reported. A second
+/// signal is fatal as usual (Unix).
+#[cfg(unix)]
+pub fn catch_interrupts() {
+    extern "C" fn handle(signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+    let handler = handle as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn catch_interrupts() {}
+
+/// Whether a graceful stop was requested by a signal.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Opens the file shared by every avgtime run with `--exclusive`. Users who
+/// cannot write to a lock file created by someone else open it read-only,
+/// which is enough to lock it.
+pub fn open_lock_file() -> io::Result<File> {
+    let path = env::temp_dir().join(LOCK_FILE);
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).or_else(|_| File::open(&path))
+}
+
+/// Takes an exclusive advisory lock on `file`, blocking until it is free
+/// when `wait` is set. Returns false when another process holds it. The
+/// lock is released when the file is closed, which also happens when
+/// avgtime is killed (Unix).
+#[cfg(unix)]
+pub fn lock(file: &File, wait: bool) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let operation = if wait { libc::LOCK_EX } else { libc::LOCK_EX | libc::LOCK_NB };
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(true);
+        }
+        let error = io::Error::last_os_error();
+        match error.kind() {
+            io::ErrorKind::WouldBlock => return Ok(false),
+            io::ErrorKind::Interrupted => continue,
+            _ => return Err(error),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn lock(_file: &File, _wait: bool) -> io::Result<bool> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "file locking is not supported on this platform"))
+}
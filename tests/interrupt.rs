@@ -0,0 +1,58 @@
+//! SIGINT and SIGTERM stop the benchmark and report the runs made so far.
+#![cfg(unix)]
+
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs avgtime in its own process group and, after the first run has
+/// finished and while the second is in progress, sends `signal` to the
+/// whole group, as a terminal does for Ctrl-C, or to avgtime alone, as
+/// `docker stop` does.
+fn interrupt(command: &str, signal: i32, whole_group: bool) -> Output {
+    let child = Command::new(env!("CARGO_BIN_EXE_avgtime"))
+        .args(["-r", "5", command])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(750));
+    let pid = child.id() as libc::pid_t;
+    assert_eq!(unsafe { libc::kill(if whole_group { -pid } else { pid }, signal) }, 0);
+    child.wait_with_output().unwrap()
+}
+
+/// Checks the report of a benchmark stopped during its second run. A
+/// signal sent to the whole group kills that run at the same moment the
+/// handler runs, so a worker may already have started the third before
+/// avgtime notices; `in_progress` lists the counts that can be discarded.
+fn check_partial_report(output: Output, in_progress: &[usize]) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stdout.contains("Completed: 1 / 5 requested"), "{}", stdout);
+    // The run killed along with avgtime is not part of the results.
+    assert!(!stdout.contains("killed by signal"), "{}", stdout);
+    assert!(stderr.contains("interrupted, reporting the 1 completed runs"), "{}", stderr);
+    assert!(in_progress.iter().any(|runs| stderr.contains(&format!("discarded {} runs that were still in progress", runs))), "{}", stderr);
+}
+
+#[test]
+fn sigterm_reports_completed_runs() {
+    check_partial_report(interrupt("sleep 0.5", libc::SIGTERM, true), &[1, 2]);
+}
+
+#[test]
+fn sigint_reports_completed_runs() {
+    check_partial_report(interrupt("sleep 0.5", libc::SIGINT, true), &[1, 2]);
+}
+
+#[test]
+fn sigterm_to_avgtime_alone_stops_the_run_in_progress() {
+    let start = Instant::now();
+    let output = interrupt("if [ {i} = 0 ]; then sleep 0.5; else exec sleep 30; fi", libc::SIGTERM, false);
+    assert!(start.elapsed() < Duration::from_secs(10), "waited for the run in progress");
+    check_partial_report(output, &[1]);
+}